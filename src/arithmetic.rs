@@ -0,0 +1,523 @@
+use crate::error::Error;
+use crate::shell::Shell;
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+/// Longest first, so that `<<=` isn't read as `<` then `<=`
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^", "|", "?", ":", "(", ")", ",",
+];
+
+const ASSIGNMENT_OPERATORS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|="];
+
+/// Binary operators from lowest to highest precedence, all left associative. `**` binds tighter still
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Reads an integer constant: decimal, hex with `0x`, or octal with a leading `0`
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse::<u64>().ok()?
+    };
+
+    // Like C, anything too big wraps around
+    let value = value as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+    loop {
+        rest = rest.trim_start();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        let len = if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let value = parse_number(&rest[..len]).ok_or_else(|| format!("{}: invalid number", &rest[..len]))?;
+            tokens.push(Token::Number(value));
+            len
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            let len = rest.find(|c: char| c != '_' && !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            let operator = OPERATORS.iter().find(|operator| rest.starts_with(**operator)).ok_or_else(|| format!("syntax error: invalid character `{}`", c))?;
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        };
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `++x` or `--x`, holding the amount to add
+    PreIncrement(String, i64),
+    /// `x++` or `x--`, holding the amount to add
+    PostIncrement(String, i64),
+    /// Holds the operator, `=` or a compound one like `+=`
+    Assign(String, &'static str, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(&Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn next_if_operator(&mut self, operators: &[&str]) -> Option<&'static str> {
+        let operator = self.peek_operator().filter(|operator| operators.contains(operator))?;
+        self.position += 1;
+        Some(operator)
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        match self.next_if_operator(&[operator]) {
+            Some(_) => Ok(()),
+            None => Err(format!("syntax error: expected `{}`", operator)),
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.assignment()?;
+        while self.next_if_operator(&[","]).is_some() {
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        let expr = self.conditional()?;
+        match self.next_if_operator(ASSIGNMENT_OPERATORS) {
+            Some(operator) => match expr {
+                Expr::Variable(name) => Ok(Expr::Assign(name, operator, Box::new(self.assignment()?))),
+                _ => Err("attempted assignment to non-variable".to_string()),
+            },
+            None => Ok(expr),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.binary(0)?;
+        if self.next_if_operator(&["?"]).is_none() {
+            return Ok(condition);
+        }
+
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY_LEVELS.len() {
+            return self.power();
+        }
+
+        let mut expr = self.binary(level + 1)?;
+        while let Some(operator) = self.next_if_operator(BINARY_LEVELS[level]) {
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.binary(level + 1)?));
+        }
+        Ok(expr)
+    }
+
+    /// `**` is right associative
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.unary()?;
+        if self.next_if_operator(&["**"]).is_some() {
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(self.power()?)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if let Some(operator) = self.next_if_operator(&["++", "--"]) {
+            let amount = if operator == "++" { 1 } else { -1 };
+            return match self.tokens.get(self.position).cloned() {
+                Some(Token::Name(name)) => {
+                    self.position += 1;
+                    Ok(Expr::PreIncrement(name, amount))
+                },
+                _ => Err(format!("syntax error: `{}` needs a variable", operator)),
+            };
+        }
+
+        if let Some(operator) = self.next_if_operator(&["+", "-", "!", "~"]) {
+            return Ok(Expr::Unary(operator, Box::new(self.unary()?)));
+        }
+
+        let expr = self.primary()?;
+        if let Expr::Variable(ref name) = expr {
+            if let Some(operator) = self.next_if_operator(&["++", "--"]) {
+                return Ok(Expr::PostIncrement(name.clone(), if operator == "++" { 1 } else { -1 }));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Name(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Operator("(")) => {
+                let expr = self.comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            Some(Token::Operator(operator)) => Err(format!("syntax error: operand expected (error token is `{}`)", operator)),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
+
+/// A variable's value as a number, where unset or empty is 0
+fn variable_value(shell: &Shell, name: &str) -> Result<i64, String> {
+    match shell.variable(name) {
+        Some(value) if !value.trim().is_empty() => parse_number(value).ok_or_else(|| format!("{}: invalid number", value)),
+        _ => Ok(0),
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match operator {
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "**" if right < 0 => return Err("exponent less than 0".to_string()),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "&" => left & right,
+        "^" => left ^ right,
+        "|" => left | right,
+        "," => right,
+        _ => unreachable!(),
+    })
+}
+
+fn eval(shell: &mut Shell, expr: &Expr) -> Result<i64, String> {
+    Ok(match *expr {
+        Expr::Number(value) => value,
+        Expr::Variable(ref name) => variable_value(shell, name)?,
+        Expr::Unary(operator, ref operand) => {
+            let value = eval(shell, operand)?;
+            match operator {
+                "+" => value,
+                "-" => value.wrapping_neg(),
+                "!" => (value == 0) as i64,
+                _ => !value,
+            }
+        },
+        // Only evaluated as far as needed, so `x && y++` leaves y alone when x is 0
+        Expr::Binary("&&", ref left, ref right) => (eval(shell, left)? != 0 && eval(shell, right)? != 0) as i64,
+        Expr::Binary("||", ref left, ref right) => (eval(shell, left)? != 0 || eval(shell, right)? != 0) as i64,
+        Expr::Binary(operator, ref left, ref right) => {
+            let left = eval(shell, left)?;
+            let right = eval(shell, right)?;
+            apply(operator, left, right)?
+        },
+        Expr::PreIncrement(ref name, amount) => {
+            let value = variable_value(shell, name)?.wrapping_add(amount);
+            shell.set_variable(name, value.to_string());
+            value
+        },
+        Expr::PostIncrement(ref name, amount) => {
+            let value = variable_value(shell, name)?;
+            shell.set_variable(name, value.wrapping_add(amount).to_string());
+            value
+        },
+        Expr::Assign(ref name, operator, ref operand) => {
+            let right = eval(shell, operand)?;
+            let value = match operator {
+                "=" => right,
+                _ => apply(&operator[..operator.len() - 1], variable_value(shell, name)?, right)?,
+            };
+            shell.set_variable(name, value.to_string());
+            value
+        },
+        Expr::Conditional(ref condition, ref then, ref otherwise) => {
+            if eval(shell, condition)? != 0 {
+                eval(shell, then)?
+            } else {
+                eval(shell, otherwise)?
+            }
+        },
+    })
+}
+
+/// Evaluates an integer expression with C's operators and precedence, reading and assigning shell variables.
+/// An empty expression is 0
+pub fn evaluate(shell: &mut Shell, expression: &str) -> Result<i64, Error> {
+    let result: Result<i64, String> = try {
+        let tokens = tokenize(expression)?;
+        if tokens.is_empty() {
+            return Ok(0);
+        }
+
+        let mut parser = Parser { tokens, position: 0 };
+        let expr = parser.comma()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            let text = match *token {
+                Token::Number(value) => value.to_string(),
+                Token::Name(ref name) => name.clone(),
+                Token::Operator(operator) => operator.to_string(),
+            };
+            Err(format!("syntax error in expression (error token is `{}`)", text))?;
+        }
+        eval(shell, &expr)?
+    };
+
+    result.map_err(|message| Error::Arithmetic(expression.trim().to_string(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::Settings;
+    use crate::shell::Options;
+
+    fn test_shell() -> Shell {
+        let settings = Settings {
+            path: Vec::new(),
+            exports: Vec::new(),
+            aliases: HashMap::new(),
+            options: Options::default(),
+            completions: HashMap::new(),
+        };
+        Shell::new("/".to_string(), settings)
+    }
+
+    fn check(cases: &[(&str, i64)]) {
+        for &(expression, expected) in cases {
+            let mut shell = test_shell();
+            match evaluate(&mut shell, expression) {
+                Ok(value) => assert_eq!(value, expected, "{:?}", expression),
+                Err(e) => panic!("{:?} failed: {}", expression, e),
+            }
+        }
+    }
+
+    /// An expression, its value, and the variables it should leave behind
+    type VariableCase<'a> = (&'a str, i64, &'a [(&'a str, &'a str)]);
+
+    /// Evaluates each expression in a fresh shell, then checks the variables it left behind
+    fn check_variables(cases: &[VariableCase]) {
+        for &(expression, expected, variables) in cases {
+            let mut shell = test_shell();
+            for name in ["x", "y", "z"] {
+                shell.unset_variable(name);
+            }
+            match evaluate(&mut shell, expression) {
+                Ok(value) => assert_eq!(value, expected, "{:?}", expression),
+                Err(e) => panic!("{:?} failed: {}", expression, e),
+            }
+            for &(name, value) in variables {
+                assert_eq!(shell.variable(name), Some(value), "{} after {:?}", name, expression);
+            }
+        }
+    }
+
+    fn check_errors(cases: &[(&str, &str)]) {
+        for &(expression, message) in cases {
+            let mut shell = test_shell();
+            match evaluate(&mut shell, expression) {
+                Ok(value) => panic!("{:?} gave {} instead of an error", expression, value),
+                Err(e) => assert_eq!(e.to_string(), message, "{:?}", expression),
+            }
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        check(&[
+            ("", 0),
+            ("  ", 0),
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("10 - 4 - 3", 3),
+            ("100 / 10 / 5", 2),
+            ("7 % 4 * 2", 6),
+            ("-2 * 3", -6),
+            ("- -1", 1),
+            ("!0 + !5", 1),
+            ("~0", -1),
+            ("1 << 2 + 1", 8),
+            ("1 < 2 == 1", 1),
+            ("6 & 3 | 8", 10),
+            ("6 ^ 3 & 1", 7),
+            ("1 | 2 && 0", 0),
+            ("0 && 1 || 1", 1),
+            ("-2 ** 2", 4),
+            ("1, 2, 3", 3),
+        ]);
+    }
+
+    #[test]
+    fn right_associativity() {
+        check(&[
+            ("2 ** 3 ** 2", 512),
+            ("2 ** 0", 1),
+        ]);
+        check_variables(&[
+            ("x = y = 3", 3, &[("x", "3"), ("y", "3")]),
+            ("x = 1, y = x += 2", 3, &[("x", "3"), ("y", "3")]),
+        ]);
+    }
+
+    #[test]
+    fn conditional() {
+        check(&[
+            ("1 ? 2 : 3", 2),
+            ("0 ? 2 : 3", 3),
+            ("0 ? 1 : 0 ? 2 : 3", 3),
+            ("1 ? 0 ? 4 : 5 : 6", 5),
+            ("1 + 1 ? 7 : 8", 7),
+        ]);
+        check_variables(&[
+            ("1 ? (x = 1) : (y = 2)", 1, &[("x", "1")]),
+            ("x = 0 ? 4 : 5", 5, &[("x", "5")]),
+        ]);
+    }
+
+    #[test]
+    fn assignment() {
+        check_variables(&[
+            ("x = 5", 5, &[("x", "5")]),
+            ("x = 5, x += 2", 7, &[("x", "7")]),
+            ("x = 5, x -= 7", -2, &[("x", "-2")]),
+            ("x = 5, x *= 3", 15, &[("x", "15")]),
+            ("x = 17, x /= 5", 3, &[("x", "3")]),
+            ("x = 17, x %= 5", 2, &[("x", "2")]),
+            ("x = 1, x <<= 4", 16, &[("x", "16")]),
+            ("x = 16, x >>= 2", 4, &[("x", "4")]),
+            ("x = 6, x &= 3", 2, &[("x", "2")]),
+            ("x = 6, x ^= 3", 5, &[("x", "5")]),
+            ("x = 6, x |= 3", 7, &[("x", "7")]),
+            ("x += 1", 1, &[("x", "1")]),
+            ("x = 1, y = x++", 1, &[("x", "2"), ("y", "1")]),
+            ("x = 1, y = ++x", 2, &[("x", "2"), ("y", "2")]),
+            ("x = 1, y = x--", 1, &[("x", "0"), ("y", "1")]),
+            ("x = 1, y = --x", 0, &[("x", "0"), ("y", "0")]),
+            ("x = 010, x + 0", 8, &[("x", "8")]),
+        ]);
+
+        let mut shell = test_shell();
+        shell.set_variable("x", "0x10".to_string());
+        shell.set_variable("y", "x".to_string());
+        assert_eq!(evaluate(&mut shell, "x + 1").ok(), Some(17));
+        assert!(evaluate(&mut shell, "y").is_err());
+    }
+
+    #[test]
+    fn short_circuit() {
+        check_variables(&[
+            ("0 && (x = 1)", 0, &[]),
+            ("1 || (x = 1)", 1, &[]),
+            ("1 && (x = 2)", 1, &[("x", "2")]),
+            ("0 || (x = 2)", 1, &[("x", "2")]),
+            ("0 && 1 / 0", 0, &[]),
+            ("1 || 1 / 0", 1, &[]),
+            ("0 ? 1 / 0 : 4", 4, &[]),
+        ]);
+
+        let mut shell = test_shell();
+        shell.unset_variable("x");
+        assert_eq!(evaluate(&mut shell, "0 && x++").ok(), Some(0));
+        assert_eq!(shell.variable("x"), None);
+    }
+
+    #[test]
+    fn numbers() {
+        check(&[
+            ("0x1f", 31),
+            ("0XfF", 255),
+            ("010", 8),
+            ("0", 0),
+            ("00", 0),
+            ("-010", -8),
+            ("9223372036854775807 + 1", i64::MIN),
+            ("-9223372036854775807 - 2", i64::MAX),
+            ("9223372036854775808", i64::MIN),
+            ("0xffffffffffffffff", -1),
+            ("2 ** 64", 0),
+            ("3 * 9223372036854775807", 9223372036854775805),
+            ("(-9223372036854775807 - 1) / -1", i64::MIN),
+        ]);
+    }
+
+    #[test]
+    fn errors() {
+        check_errors(&[
+            ("1 / 0", "1 / 0: division by zero"),
+            ("5 % (2 - 2)", "5 % (2 - 2): division by zero"),
+            ("x = 1, x /= 0", "x = 1, x /= 0: division by zero"),
+            ("2 ** -1", "2 ** -1: exponent less than 0"),
+            ("08", "08: 08: invalid number"),
+            ("0xg", "0xg: 0xg: invalid number"),
+            (" 1 + ", "1 +: syntax error: operand expected"),
+            ("1 2", "1 2: syntax error in expression (error token is `2`)"),
+            ("(1", "(1: syntax error: expected `)`"),
+            ("1 ? 2", "1 ? 2: syntax error: expected `:`"),
+            ("1 = 2", "1 = 2: attempted assignment to non-variable"),
+            ("++1", "++1: syntax error: `++` needs a variable"),
+            ("1 $ 2", "1 $ 2: syntax error: invalid character `$`"),
+            ("* 2", "* 2: syntax error: operand expected (error token is `*`)"),
+        ]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::convert::TryFrom;
+
+use crate::parser::{Word, WordPart};
+
+/// A word broken down so that braces can be found: unquoted characters one at a time, anything else whole
+#[derive(Clone, Copy)]
+enum Unit<'a> {
+    Char(char),
+    Part(&'a WordPart),
+}
+
+fn units(word: &Word) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+    for part in word.parts.iter() {
+        match *part {
+            WordPart::Bare(ref text) => units.extend(text.chars().map(Unit::Char)),
+            ref part => units.push(Unit::Part(part)),
+        }
+    }
+    units
+}
+
+fn to_word(units: &[Unit]) -> Word {
+    let mut parts = Vec::new();
+    for unit in units {
+        match *unit {
+            Unit::Char(c) => match parts.last_mut() {
+                Some(WordPart::Bare(ref mut text)) => text.push(c),
+                _ => parts.push(WordPart::Bare(c.to_string())),
+            },
+            Unit::Part(part) => parts.push(part.clone()),
+        }
+    }
+    Word { parts }
+}
+
+/// The values of a `{x..y}` or `{x..y..step}` sequence, counting integers or single letters.
+/// Integers written with a leading zero are padded to the same width
+fn sequence(text: &str) -> Option<Vec<String>> {
+    let mut bounds = text.split("..");
+    let (start, end) = (bounds.next()?, bounds.next()?);
+    let step = match bounds.next() {
+        Some(step) => step.parse::<i64>().ok()?.checked_abs()?.max(1),
+        None => 1,
+    };
+    if bounds.next().is_some() {
+        return None;
+    }
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |bound: &str| bound.trim_start_matches('-').len() > 1 && bound.trim_start_matches('-').starts_with('0');
+        let width = if padded(start) || padded(end) { start.len().max(end.len()) } else { 0 };
+        return Some(steps(first, last, step)?.map(|n| {
+            if n < 0 {
+                format!("-{:0width$}", n.unsigned_abs(), width = width.saturating_sub(1))
+            } else {
+                format!("{:0width$}", n, width = width)
+            }
+        }).collect());
+    }
+
+    let mut start_chars = start.chars();
+    let mut end_chars = end.chars();
+    match (start_chars.next(), start_chars.next(), end_chars.next(), end_chars.next()) {
+        (Some(first), None, Some(last), None) if first.is_ascii_alphabetic() && last.is_ascii_alphabetic() => {
+            Some(steps(first as i64, last as i64, step)?.map(|c| (c as u8 as char).to_string()).collect())
+        },
+        _ => None,
+    }
+}
+
+/// From `first` to `last` inclusive, in whichever direction that is. None if there are too many to count
+fn steps(first: i64, last: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    let count = i64::try_from((first.abs_diff(last) / step as u64).checked_add(1)?).ok()?;
+    let step = if last < first { -step } else { step };
+    Some((0..count).map(move |i| first + i * step))
+}
+
+/// What a brace expression starting at `open` stands for, and where it ends. None if it isn't one
+fn alternatives<'a>(units: &[Unit<'a>], open: usize) -> Option<(Vec<Vec<Unit<'a>>>, usize)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut close = None;
+    for (i, unit) in units.iter().enumerate().skip(open + 1) {
+        match *unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') if depth == 0 => {
+                close = Some(i);
+                break;
+            },
+            Unit::Char('}') => depth -= 1,
+            Unit::Char(',') if depth == 0 => commas.push(i),
+            _ => (),
+        }
+    }
+    let close = close?;
+
+    if !commas.is_empty() {
+        let mut alternatives = Vec::with_capacity(commas.len() + 1);
+        let mut start = open + 1;
+        for &end in commas.iter().chain(std::iter::once(&close)) {
+            alternatives.push(units[start..end].to_vec());
+            start = end + 1;
+        }
+        return Some((alternatives, close));
+    }
+
+    // A sequence has to be written out plainly, without any quoting
+    let mut text = String::new();
+    for unit in units[open + 1..close].iter() {
+        match *unit {
+            Unit::Char(c) => text.push(c),
+            Unit::Part(_) => return None,
+        }
+    }
+    let values = sequence(&text)?;
+    Some((values.into_iter().map(|value| value.chars().map(Unit::Char).collect()).collect(), close))
+}
+
+fn expand_units<'a>(units: &[Unit<'a>], words: &mut Vec<Word>) {
+    for (open, unit) in units.iter().enumerate() {
+        if let Unit::Char('{') = *unit {
+            if let Some((alternatives, close)) = alternatives(units, open) {
+                // Expanding each whole result again takes care of nested braces and any that come later
+                for alternative in alternatives {
+                    let mut expanded = units[..open].to_vec();
+                    expanded.extend(alternative);
+                    expanded.extend_from_slice(&units[close + 1..]);
+                    expand_units(&expanded, words);
+                }
+                return;
+            }
+        }
+    }
+
+    words.push(to_word(units));
+}
+
+/// Expands `{a,b,c}` and `{x..y..step}` into a word for each. Quoted braces are left alone
+pub fn expand(word: &Word) -> Vec<Word> {
+    let has_brace = word.parts.iter().any(|part| matches!(*part, WordPart::Bare(ref text) if text.contains('{')));
+    if !has_brace {
+        return vec![word.clone()];
+    }
+
+    let mut words = Vec::new();
+    expand_units(&units(word), &mut words);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    /// Checks what each word expands to, written back out with its quoting
+    fn check(cases: &[(&str, &[&str])]) {
+        for &(input, expected) in cases {
+            let word = match lexer::parse_word(input) {
+                Ok(word) => word,
+                Err(e) => panic!("{:?} failed to parse: {}", input, e),
+            };
+            let words: Vec<String> = expand(&word).iter().map(|word| word.to_string()).collect();
+            assert_eq!(words, expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn alternatives() {
+        check(&[
+            ("a{b,c}d", &["abd", "acd"]),
+            ("{a,b}{1,2}", &["a1", "a2", "b1", "b2"]),
+            ("{a,{b,c}d}e", &["ae", "bde", "cde"]),
+            ("x{,y}", &["x", "xy"]),
+            ("{,}", &["", ""]),
+            ("{a,b", &["{a,b"]),
+            ("a}b,c", &["a}b,c"]),
+            ("{a}", &["{a}"]),
+            ("{}", &["{}"]),
+            ("{{a,b}", &["{a", "{b"]),
+        ]);
+    }
+
+    #[test]
+    fn sequences() {
+        check(&[
+            ("{1..4}", &["1", "2", "3", "4"]),
+            ("{3..1}", &["3", "2", "1"]),
+            ("{-2..1}", &["-2", "-1", "0", "1"]),
+            ("{1..10..3}", &["1", "4", "7", "10"]),
+            ("{10..1..-4}", &["10", "6", "2"]),
+            ("{1..3..0}", &["1", "2", "3"]),
+            ("{01..10..4}", &["01", "05", "09"]),
+            ("{-05..5..5}", &["-05", "000", "005"]),
+            ("{a..e..2}", &["a", "c", "e"]),
+            ("{C..A}", &["C", "B", "A"]),
+            ("{5..5}", &["5"]),
+            ("x{1..2}{a..b}", &["x1a", "x1b", "x2a", "x2b"]),
+            ("{1..2..3..4}", &["{1..2..3..4}"]),
+            ("{1..b}", &["{1..b}"]),
+            ("{aa..b}", &["{aa..b}"]),
+            ("{1..}", &["{1..}"]),
+            ("{-9223372036854775808..9223372036854775807}", &["{-9223372036854775808..9223372036854775807}"]),
+            ("{9223372036854775807..-9223372036854775808..2}", &["{9223372036854775807..-9223372036854775808..2}"]),
+            ("{9223372036854775806..9223372036854775807}", &["9223372036854775806", "9223372036854775807"]),
+            ("{-9223372036854775808..-9223372036854775807}", &["-9223372036854775808", "-9223372036854775807"]),
+        ]);
+    }
+
+    #[test]
+    fn quoting() {
+        check(&[
+            ("'{a,b}'", &["'{a,b}'"]),
+            ("\\{a,b}", &["'{'a,b}"]),
+            ("{a',b'}", &["{a',b'}"]),
+            ("{'a b',c}", &["'a b'", "c"]),
+            ("{$x,\"$y\"}z", &["${x}z", "\"${y}\"z"]),
+            ("{1'..'3}", &["{1'..'3}"]),
+        ]);
+    }
+}
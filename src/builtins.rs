@@ -0,0 +1,621 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::arithmetic;
+use crate::complete;
+use crate::history;
+use crate::jobs;
+use crate::parser;
+use crate::path::{self, Resolution};
+use crate::shell::{CommandKind, Options, Shell};
+
+pub const NAMES: &[&str] = &["bg", "cd", "command", "complete", "exit", "export", "fg", "hash", "history", "jobs", "let", "rehash", "set", "type", "unset", "wait", "which"];
+
+pub fn is_builtin(name: &str) -> bool {
+    NAMES.contains(&name)
+}
+
+/// Runs the builtin named by `argv[0]`, returning its exit status
+pub fn run(shell: &mut Shell, argv: &[String]) -> i32 {
+    match argv[0].as_str() {
+        "bg" => bg(shell, argv),
+        "cd" => cd(shell, argv),
+        "command" => command(shell, argv),
+        "complete" => complete(shell, argv),
+        "exit" => exit(shell, argv),
+        "export" => export(shell, argv),
+        "fg" => fg(shell, argv),
+        "hash" => hash(shell, argv),
+        "history" => history(shell, argv),
+        "jobs" => jobs(shell, argv),
+        "let" => let_(shell, argv),
+        "rehash" => {
+            shell.command_hash.clear();
+            0
+        },
+        "set" => set(shell, argv),
+        "type" => type_(shell, argv),
+        "unset" => unset(shell, argv),
+        "wait" => wait(shell, argv),
+        "which" => which(shell, argv),
+        _ => unreachable!(),
+    }
+}
+
+/// `cd -` goes back to the last directory. Keeps PWD and OLDPWD up to date
+fn cd(shell: &mut Shell, argv: &[String]) -> i32 {
+    let target = if argv.len() > 2 {
+        eprintln!("cd: Expected 0 or 1 arguments, got {}", argv.len() - 1);
+        return 1;
+    } else if argv.len() == 1 {
+        shell.variable("HOME").unwrap_or(&shell.home_dir).to_string()
+    } else if argv[1] == "-" {
+        match shell.variable("OLDPWD") {
+            Some(dir) => {
+                println!("{}", dir);
+                dir.to_string()
+            },
+            None => {
+                eprintln!("cd: OLDPWD not set");
+                return 1;
+            }
+        }
+    } else {
+        argv[1].clone()
+    };
+
+    let previous = env::current_dir().ok();
+    if let Err(e) = env::set_current_dir(Path::new(&target)) {
+        eprintln!("cd: {}", e);
+        return 1;
+    }
+
+    let previous = shell.variable("PWD").map(|dir| dir.to_string()).or_else(|| previous.map(|dir| dir.to_string_lossy().into_owned()));
+    if let Some(previous) = previous {
+        shell.set_variable("OLDPWD", previous);
+    }
+    if let Ok(dir) = env::current_dir() {
+        shell.set_variable("PWD", dir.to_string_lossy().into_owned());
+    }
+
+    0
+}
+
+fn set(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut args = argv[1..].iter();
+
+    if argv.len() == 1 || (argv.len() == 2 && argv[1] == "-o") {
+        for name in Options::NAMES {
+            let state = if shell.options.get(name) == Some(true) { "on" } else { "off" };
+            println!("{:<15} {}", name, state);
+        }
+        return 0;
+    }
+
+    while let Some(flag) = args.next() {
+        let enable = match flag.as_str() {
+            "-o" => true,
+            "+o" => false,
+            // Everything after `--` replaces the positional parameters
+            "--" => {
+                shell.positional = args.cloned().collect();
+                return 0;
+            },
+            _ => {
+                eprintln!("set: unknown flag {}, expected -o, +o, or --", flag);
+                return 2;
+            }
+        };
+
+        match args.next() {
+            Some(name) => match shell.options.get_mut(name) {
+                Some(option) => *option = enable,
+                None => {
+                    eprintln!("set: {}: invalid option name", name);
+                    return 1;
+                }
+            },
+            None => {
+                eprintln!("set: {} requires an option name", flag);
+                return 2;
+            }
+        }
+    }
+
+    0
+}
+
+/// `exit [status]` ends the shell, with the last command's status if none is given
+fn exit(shell: &mut Shell, argv: &[String]) -> i32 {
+    let status = match argv.get(1) {
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(status) => status & 0xff,
+            Err(_) => {
+                eprintln!("exit: {}: numeric argument required", arg);
+                2
+            }
+        },
+        None => shell.exit_status,
+    };
+
+    let _ = io::stdout().flush();
+    std::process::exit(status);
+}
+
+/// `export NAME=value` or `export NAME` marks variables to be passed on to commands, `-n` stops passing them on
+fn export(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut args = &argv[1..];
+    let unexport = args.first().map(|arg| arg == "-n").unwrap_or(false);
+    if unexport {
+        args = &args[1..];
+    }
+
+    if args.is_empty() && !unexport {
+        let mut names: Vec<&String> = shell.variables.iter().filter(|(_, variable)| variable.exported).map(|(name, _)| name).collect();
+        names.sort();
+        for name in names {
+            println!("export {}='{}'", name, shell.variables[name].value.replace('\'', "'\\''"));
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !parser::is_name(name) {
+            eprintln!("export: `{}': not a valid identifier", arg);
+            status = 1;
+            continue;
+        }
+
+        if let Some(value) = value {
+            shell.set_variable(name, value.to_string());
+        } else if !unexport && !shell.variables.contains_key(name) {
+            shell.set_variable(name, String::new());
+        }
+        if let Some(variable) = shell.variables.get_mut(name) {
+            variable.exported = !unexport;
+        }
+    }
+
+    status
+}
+
+/// Evaluates each argument as an arithmetic expression. Succeeds if the last one isn't 0
+fn let_(shell: &mut Shell, argv: &[String]) -> i32 {
+    if argv.len() == 1 {
+        eprintln!("let: expected an expression");
+        return 2;
+    }
+
+    let mut value = 0;
+    for expression in argv[1..].iter() {
+        value = match arithmetic::evaluate(shell, expression) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("let: {}", e);
+                return 2;
+            }
+        };
+    }
+
+    if value != 0 { 0 } else { 1 }
+}
+
+fn unset(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut status = 0;
+    for name in argv[1..].iter() {
+        if !parser::is_name(name) {
+            eprintln!("unset: `{}': not a valid identifier", name);
+            status = 1;
+            continue;
+        }
+        shell.unset_variable(name);
+    }
+    status
+}
+
+/// Finds the job named by the first argument, or the current job if there are no arguments
+fn job_from_args(shell: &Shell, name: &str, argv: &[String]) -> Option<usize> {
+    let id = match argv.get(1) {
+        Some(spec) => shell.jobs.parse_spec(spec),
+        None => shell.jobs.current(),
+    };
+
+    if id.is_none() {
+        eprintln!("{}: {}: no such job", name, argv.get(1).map(|spec| spec.as_str()).unwrap_or("current"));
+    }
+
+    id
+}
+
+/// Lists history, or the last N entries, searches it with -s, or clears it with -c
+fn history(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut search = None;
+    let mut count = shell.history.entries.len();
+    match argv.get(1).map(|arg| arg.as_str()) {
+        None => (),
+        Some("-c") => {
+            if let Err(e) = shell.history.clear() {
+                eprintln!("history: {}", e);
+                return 1;
+            }
+            return 0;
+        },
+        Some("-s") => match argv.get(2) {
+            Some(text) => search = Some(text.as_str()),
+            None => {
+                eprintln!("history: -s requires something to search for");
+                return 2;
+            }
+        },
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(n) => count = n,
+            Err(_) => {
+                eprintln!("history: unknown argument {}, expected -c, -s, or a count", arg);
+                return 2;
+            }
+        },
+    }
+
+    let entries = &shell.history.entries;
+    for (i, entry) in entries.iter().enumerate().skip(entries.len().saturating_sub(count)) {
+        if search.map(|text| entry.line.contains(text)).unwrap_or(true) {
+            println!("{:>5}  {}  {}", i + 1, history::format_timestamp(entry.timestamp), entry.line);
+        }
+    }
+
+    0
+}
+
+fn jobs(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut show_pids = false;
+    let mut only_pids = false;
+    for arg in argv[1..].iter() {
+        match arg.as_str() {
+            "-l" => show_pids = true,
+            "-p" => only_pids = true,
+            _ => {
+                eprintln!("jobs: unknown flag {}, expected -l or -p", arg);
+                return 2;
+            }
+        }
+    }
+
+    shell.jobs.reap();
+    for job in shell.jobs.iter() {
+        if only_pids {
+            for process in job.processes.iter().filter(|process| process.pid != 0) {
+                println!("{}", process.pid);
+            }
+            continue;
+        }
+
+        print!("[{}]{} ", job.id, shell.jobs.marker(job.id));
+        if show_pids {
+            let pids: Vec<String> = job.processes.iter().filter(|process| process.pid != 0).map(|process| process.pid.to_string()).collect();
+            print!("{} ", pids.join(","));
+        }
+        println!("{:<24}{}", job.state_description(shell.options.pipefail), job.command);
+    }
+
+    // Finished jobs have been reported now, so they don't need to be announced again
+    shell.jobs.take_finished();
+
+    0
+}
+
+fn fg(shell: &mut Shell, argv: &[String]) -> i32 {
+    let id = match job_from_args(shell, "fg", argv) {
+        Some(id) => id,
+        None => return 1,
+    };
+
+    let job = shell.jobs.remove(id).unwrap();
+    println!("{}", job.command);
+    jobs::foreground(shell, job, true)
+}
+
+fn bg(shell: &mut Shell, argv: &[String]) -> i32 {
+    let id = match job_from_args(shell, "bg", argv) {
+        Some(id) => id,
+        None => return 1,
+    };
+
+    let job = shell.jobs.get_mut(id).unwrap();
+    if !job.is_stopped() {
+        eprintln!("bg: job {} already in background", id);
+        return 0;
+    }
+
+    job.resume();
+    println!("[{}] {} &", id, job.command);
+    0
+}
+
+fn wait(shell: &mut Shell, argv: &[String]) -> i32 {
+    if argv.len() == 1 {
+        let ids: Vec<usize> = shell.jobs.iter().map(|job| job.id).collect();
+        for id in ids {
+            let mut job = shell.jobs.remove(id).unwrap();
+            jobs::wait_for(&mut job);
+            if job.is_stopped() {
+                shell.jobs.add(job);
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in argv[1..].iter() {
+        let id = if arg.starts_with('%') {
+            shell.jobs.parse_spec(arg)
+        } else {
+            match arg.parse::<libc::pid_t>() {
+                Ok(pid) => shell.jobs.iter().find(|job| job.processes.iter().any(|process| process.pid == pid)).map(|job| job.id),
+                Err(_) => {
+                    eprintln!("wait: {}: not a pid or valid job spec", arg);
+                    status = 2;
+                    continue;
+                }
+            }
+        };
+
+        match id {
+            Some(id) => {
+                let mut job = shell.jobs.remove(id).unwrap();
+                jobs::wait_for(&mut job);
+                status = job.status(shell.options.pipefail);
+                if job.is_stopped() {
+                    shell.jobs.add(job);
+                }
+            },
+            None => {
+                eprintln!("wait: {}: no such job or child of this shell", arg);
+                status = 127;
+            }
+        }
+    }
+
+    status
+}
+
+fn hash(shell: &mut Shell, argv: &[String]) -> i32 {
+    if argv.len() == 1 {
+        let entries = shell.command_hash.sorted();
+        if entries.is_empty() {
+            println!("hash: hash table empty");
+            return 0;
+        }
+
+        println!("hits    command");
+        for (_, entry) in entries {
+            println!("{:>4}    {}", entry.hits, entry.path.display());
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    let mut args = argv[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-r" => shell.command_hash.clear(),
+            "-d" => {
+                for name in args.by_ref() {
+                    if !shell.command_hash.remove(name) {
+                        eprintln!("hash: {}: not found", name);
+                        status = 1;
+                    }
+                }
+            },
+            name if name.starts_with('-') => {
+                eprintln!("hash: unknown flag {}, expected -r or -d", name);
+                return 2;
+            },
+            // Builtins and paths are never hashed
+            name if is_builtin(name) || name.contains('/') => (),
+            name => match shell.command_hash.add(name, &shell.path_list) {
+                Resolution::Found(_) => (),
+                Resolution::NotExecutable(path) => {
+                    eprintln!("hash: {}: Permission denied", path.display());
+                    status = 1;
+                },
+                Resolution::NotFound => {
+                    eprintln!("hash: {}: not found", name);
+                    status = 1;
+                },
+            },
+        }
+    }
+
+    status
+}
+
+/// A leading backslash skips alias lookup, just like it does when running the command
+fn split_alias_bypass(name: &str) -> (&str, bool) {
+    match name.strip_prefix('\\') {
+        Some(rest) => (rest, false),
+        None => (name, true),
+    }
+}
+
+fn type_(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut kind_only = false;
+    let mut path_only = false;
+    let mut all = false;
+    let mut names = Vec::new();
+    for arg in argv[1..].iter() {
+        match arg.as_str() {
+            "-t" => kind_only = true,
+            "-p" => path_only = true,
+            "-a" => all = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                eprintln!("type: unknown flag {}, expected -t, -p, or -a", flag);
+                return 2;
+            },
+            name => names.push(name),
+        }
+    }
+
+    let mut status = 0;
+    for given in names {
+        let (name, use_aliases) = split_alias_bypass(given);
+        let mut found = false;
+
+        if use_aliases {
+            if let Some(replacement) = shell.aliases.get(name) {
+                found = true;
+                if kind_only {
+                    println!("alias");
+                } else if !path_only {
+                    println!("{} is an alias for {}", given, replacement);
+                }
+            }
+        }
+
+        if (all || !found) && is_builtin(name) {
+            found = true;
+            if kind_only {
+                println!("builtin");
+            } else if !path_only {
+                println!("{} is a shell builtin", given);
+            }
+        }
+
+        if all || !found {
+            let paths = if all {
+                path::resolve_all(name, &shell.path_list)
+            } else {
+                match shell.command_hash.peek(name, &shell.path_list) {
+                    Resolution::Found(path) => vec![path],
+                    _ => Vec::new(),
+                }
+            };
+
+            for path in paths {
+                found = true;
+                if kind_only {
+                    println!("file");
+                } else if path_only {
+                    println!("{}", path.display());
+                } else {
+                    println!("{} is {}", given, path.display());
+                }
+            }
+        }
+
+        if !found {
+            if !kind_only && !path_only {
+                eprintln!("type: {}: not found", given);
+            }
+            status = 1;
+        }
+    }
+
+    status
+}
+
+fn which(shell: &mut Shell, argv: &[String]) -> i32 {
+    let mut status = 0;
+    for given in argv[1..].iter() {
+        let (name, use_aliases) = split_alias_bypass(given);
+        match shell.find_command(name, use_aliases) {
+            CommandKind::Alias(replacement) => println!("{}: aliased to {}", given, replacement),
+            CommandKind::Builtin => println!("{}: shell builtin", given),
+            CommandKind::Program(Resolution::Found(path)) => println!("{}", path.display()),
+            CommandKind::Program(_) => {
+                eprintln!("which: {}: not found", given);
+                status = 1;
+            },
+        }
+    }
+
+    status
+}
+
+/// `command -v` prints how each name resolves in a form that can be fed back to the shell, `command -V` explains it like `type`
+fn command(shell: &mut Shell, argv: &[String]) -> i32 {
+    match argv.get(1).map(|flag| flag.as_str()) {
+        Some("-v") => (),
+        Some("-V") => {
+            let mut type_argv = vec!["type".to_string()];
+            type_argv.extend(argv[2..].iter().cloned());
+            return type_(shell, &type_argv);
+        },
+        Some(flag) if flag.starts_with('-') => {
+            eprintln!("command: unknown flag {}, expected -v or -V", flag);
+            return 2;
+        },
+        // Running a command is handled before we get here, so there's nothing to do
+        _ => return 0,
+    }
+
+    let mut status = 0;
+    for given in argv[2..].iter() {
+        let (name, use_aliases) = split_alias_bypass(given);
+        match shell.find_command(name, use_aliases) {
+            CommandKind::Alias(replacement) => println!("alias {}='{}'", name, replacement.replace('\'', "'\\''")),
+            CommandKind::Builtin => println!("{}", name),
+            CommandKind::Program(Resolution::Found(path)) => println!("{}", path.display()),
+            CommandKind::Program(_) => status = 1,
+        }
+    }
+
+    status
+}
+
+/// Registers where Tab finds candidates for a command's arguments. `-p` prints specs, `-r` removes them
+fn complete(shell: &mut Shell, argv: &[String]) -> i32 {
+    match argv.get(1).map(|flag| flag.as_str()) {
+        None | Some("-p") => {
+            let names: Vec<&String> = if argv.len() > 2 { argv[2..].iter().collect() } else {
+                let mut names: Vec<&String> = shell.completions.keys().collect();
+                names.sort();
+                names
+            };
+
+            let mut status = 0;
+            for name in names {
+                match shell.completions.get(name) {
+                    Some(spec) => println!("complete {} {}", spec, name),
+                    None => {
+                        eprintln!("complete: {}: no completion specification", name);
+                        status = 1;
+                    }
+                }
+            }
+            status
+        },
+        Some("-r") => {
+            if argv.len() == 2 {
+                shell.completions.clear();
+                return 0;
+            }
+
+            let mut status = 0;
+            for name in argv[2..].iter() {
+                if shell.completions.remove(name).is_none() {
+                    eprintln!("complete: {}: no completion specification", name);
+                    status = 1;
+                }
+            }
+            status
+        },
+        _ => match complete::parse_spec(&argv[1..]) {
+            Ok((spec, names)) => {
+                for name in names {
+                    shell.completions.insert(name, spec.clone());
+                }
+                0
+            },
+            Err(e) => {
+                eprintln!("complete: {}", e);
+                2
+            }
+        },
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::builtins;
+use crate::error;
+use crate::glob;
+use crate::lexer::{self, Token};
+use crate::path::Resolution;
+use crate::shell::Shell;
+
+/// Where the candidates for a command's arguments come from, registered with `complete`
+#[derive(Clone, Default)]
+pub struct Spec {
+    /// A fixed list of words
+    pub words: Vec<String>,
+    /// Run with the command name, the word being completed, and the word before it. Each line it prints is a candidate.
+    /// Killed if it takes more than a couple of seconds
+    pub command: Option<String>,
+    /// Files matching any of these patterns
+    pub globs: Vec<String>,
+}
+
+/// Quotes text so the lexer would read it back as one word
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut flags = Vec::new();
+        if !self.words.is_empty() {
+            flags.push(format!("-W {}", quote(&self.words.join(" "))));
+        }
+        if let Some(ref command) = self.command {
+            flags.push(format!("-C {}", quote(command)));
+        }
+        for pattern in self.globs.iter() {
+            flags.push(format!("-G {}", quote(pattern)));
+        }
+        write!(f, "{}", flags.join(" "))
+    }
+}
+
+/// Parses `complete` style arguments, `-W words`, `-C command`, and `-G pattern` followed by command names
+pub fn parse_spec(args: &[String]) -> Result<(Spec, Vec<String>), String> {
+    let mut spec = Spec::default();
+    let mut args = args.iter();
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        let flag = match arg.as_str() {
+            "-W" | "-C" | "-G" => arg.as_str(),
+            flag if flag.starts_with('-') => return Err(format!("unknown flag {}, expected -W, -C, or -G", flag)),
+            name => {
+                names.push(name.to_string());
+                continue;
+            }
+        };
+
+        let value = args.next().ok_or_else(|| format!("{} requires an argument", flag))?;
+        match flag {
+            "-W" => spec.words.extend(value.split_whitespace().map(|word| word.to_string())),
+            "-C" => spec.command = Some(value.clone()),
+            _ => spec.globs.push(value.clone()),
+        }
+    }
+
+    if names.is_empty() {
+        return Err("expected the names of commands to complete".to_string());
+    }
+
+    Ok((spec, names))
+}
+
+/// What Tab can replace the word before the cursor with
+pub struct Completion {
+    /// Where the word being completed starts, in characters
+    pub start: usize,
+    pub candidates: Vec<String>,
+}
+
+/// Characters that have to be escaped for a file name to come through the lexer unchanged
+const SPECIAL_CHARACTERS: &str = " \t\\'\"|&;<>()$`*?[]#~{}!";
+
+/// Escapes a completed word, except for a leading `~` which the user typed to mean their home directory
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        if SPECIAL_CHARACTERS.contains(c) && !(i == 0 && c == '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Undoes backslash escapes and drops quotes, close enough to what the lexer would produce for a partly typed word
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            '\'' | '"' => (),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Where the word ending at the end of `text` starts, in characters, and where its command starts
+fn current_word(text: &[char]) -> (usize, usize) {
+    let mut start = 0;
+    let mut command_start = 0;
+    let mut escaped = false;
+    let mut quote = None;
+    for (i, &c) in text.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (c, quote) {
+            ('\\', None) | ('\\', Some('"')) => escaped = true,
+            ('\'', None) | ('"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if "|&;(".contains(c) => {
+                start = i + 1;
+                command_start = i + 1;
+            },
+            (c, None) if c.is_whitespace() || "<>".contains(c) => start = i + 1,
+            _ => (),
+        }
+    }
+
+    (start, command_start)
+}
+
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => !metadata.is_dir() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Files starting with `prefix`, which may include a directory and a leading `~`. Directories get a trailing `/`
+pub fn complete_path(shell: &Shell, prefix: &str, executables_only: bool) -> Vec<String> {
+    let (directory, file_prefix) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+
+    let search_directory = if directory.is_empty() {
+        ".".to_string()
+    } else if directory.starts_with("~/") {
+        format!("{}{}", shell.home_dir, &directory[1..])
+    } else {
+        directory.to_string()
+    };
+
+    let entries = match fs::read_dir(&search_directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden files only show up when asked for
+        if !name.starts_with(file_prefix) || (name.starts_with('.') && !file_prefix.starts_with('.')) {
+            continue;
+        }
+
+        let path = entry.path();
+        let candidate = format!("{}{}", directory, name);
+        if path.is_dir() {
+            candidates.push(format!("{}/", candidate));
+        } else if !executables_only || is_executable(&path) {
+            candidates.push(candidate);
+        }
+    }
+
+    candidates.sort();
+    candidates
+}
+
+/// Builtins, aliases, and programs in the PATH starting with `prefix`
+pub fn complete_command(shell: &Shell, prefix: &str) -> Vec<String> {
+    let mut candidates = BTreeSet::new();
+    candidates.extend(builtins::NAMES.iter().filter(|name| name.starts_with(prefix)).map(|name| name.to_string()));
+    candidates.extend(shell.aliases.keys().filter(|name| name.starts_with(prefix)).cloned());
+
+    for directory in shell.path_list.iter() {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && !candidates.contains(&name) && is_executable(&entry.path()) {
+                candidates.insert(name);
+            }
+        }
+    }
+
+    candidates.into_iter().collect()
+}
+
+/// How long a `-C` command gets before it's killed, since the editor can't do anything while it runs
+const COMPLETER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs a `-C` command with the shell's PATH and environment, plus COMP_LINE, returning what it printed.
+/// Nothing if it can't be run or takes too long
+fn run_completer(shell: &Shell, command: &str, args: &[&str], line: &str) -> String {
+    let mut argv: Vec<String> = match lexer::tokenize(command) {
+        Ok(tokens) => tokens.iter().filter_map(|token| match *token {
+            Token::Word(ref word) => Some(word.text()),
+            _ => None,
+        }).collect(),
+        Err(_) => return String::new(),
+    };
+    if argv.is_empty() {
+        return String::new();
+    }
+
+    let program = match shell.command_hash.peek(&argv.remove(0), &shell.path_list) {
+        Resolution::Found(path) => path,
+        _ => return String::new(),
+    };
+
+    let environment = shell.environment(&[("COMP_LINE".to_string(), line.to_string())]);
+    let mut child = match Command::new(program)
+        .args(argv)
+        .args(args)
+        .env_clear()
+        .envs(environment.iter().filter_map(|variable| variable.to_str().ok()?.split_once('=')))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn() {
+        Ok(child) => child,
+        Err(_) => return String::new(),
+    };
+
+    let mut stdout = child.stdout.take().unwrap();
+    let deadline = Instant::now() + COMPLETER_TIMEOUT;
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let finished = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut fds = libc::pollfd { fd: stdout.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut fds, 1, remaining.as_millis() as libc::c_int) } {
+            -1 if error::errno() == libc::EINTR => continue,
+            -1 | 0 => break false,
+            _ => (),
+        }
+
+        match stdout.read(&mut buffer) {
+            Ok(0) => break true,
+            Ok(len) => output.extend_from_slice(&buffer[..len]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => break false,
+        }
+    };
+
+    if !finished {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+
+    if finished {
+        String::from_utf8_lossy(&output).into_owned()
+    } else {
+        String::new()
+    }
+}
+
+/// Candidates from a completion spec
+fn complete_spec(shell: &Shell, spec: &Spec, line: &str, name: &str, word: &str, previous: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = spec.words.iter().filter(|candidate| candidate.starts_with(word)).cloned().collect();
+
+    if let Some(ref command) = spec.command {
+        let output = run_completer(shell, command, &[name, word, previous], line);
+        candidates.extend(output.lines().filter(|candidate| candidate.starts_with(word)).map(|candidate| candidate.to_string()));
+    }
+
+    if !spec.globs.is_empty() {
+        for candidate in complete_path(shell, word, false) {
+            // Directories are kept so that it's possible to get to matches further down
+            let file_name = candidate.rsplit('/').next().unwrap_or("");
+            if candidate.ends_with('/') || spec.globs.iter().any(|pattern| glob::matches(pattern, file_name)) {
+                candidates.push(candidate);
+            }
+        }
+    }
+
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Completes the word ending at the end of `text`, the part of the line before the cursor
+pub fn complete(shell: &Shell, text: &[char]) -> Completion {
+    let (start, command_start) = current_word(text);
+    let word = unescape(&text[start..].iter().collect::<String>());
+    let command_position = text[command_start..start].iter().all(|c| c.is_whitespace());
+
+    let candidates = if command_position && !word.contains('/') {
+        complete_command(shell, &word)
+    } else if command_position {
+        complete_path(shell, &word, true)
+    } else {
+        let line: String = text[command_start..].iter().collect();
+        let before: String = text[command_start..start].iter().collect();
+        let words: Vec<String> = before.split_whitespace().map(unescape).collect();
+        let name = words.first().map(|name| name.rsplit('/').next().unwrap_or(name)).unwrap_or("");
+        let candidates = match shell.completions.get(name) {
+            Some(spec) => complete_spec(shell, spec, &line, name, &word, words.last().map(|word| word.as_str()).unwrap_or("")),
+            None => Vec::new(),
+        };
+
+        // Falls back to files when there's no spec, or the spec has nothing to offer
+        if candidates.is_empty() {
+            complete_path(shell, &word, false)
+        } else {
+            candidates
+        }
+    };
+
+    Completion { start, candidates: candidates.iter().map(|candidate| escape(candidate)).collect() }
+}
@@ -1,67 +1,40 @@
-use std::{self, env};
+use std::env;
 use std::collections::HashMap;
-use std::convert::From;
 use std::path::PathBuf;
-use std::ffi::{self, CString};
 use std::fs::File;
-use std::io::{self, BufReader, BufRead};
+use std::io::{BufReader, BufRead};
 
-pub enum Error {
-    IoError(io::Error),
-    Utf8Error(std::str::Utf8Error),
-    IntoStringError(ffi::IntoStringError),
-    ParseError((String, usize)),
-    NulError(ffi::NulError)
-}
-
-impl From<io::Error> for Error {
-    fn from(e: io::Error) -> Error {
-        Error::IoError(e)
-    }
-}
-
-impl From<ffi::IntoStringError> for Error {
-    fn from(e: ffi::IntoStringError) -> Error {
-        Error::IntoStringError(e)
-    }
-}
-
-impl From<ffi::NulError> for Error {
-    fn from(e: ffi::NulError) -> Error {
-        Error::NulError(e)
-    }
-}
-
-impl From<std::str::Utf8Error> for Error {
-    fn from(e: std::str::Utf8Error) -> Error {
-        Error::Utf8Error(e)
-    }
-}
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
-        match *self {
-            Error::IoError(ref e) => write!(f, "Encountered I/O error while attempting to load .trashrc: {}.", e),
-            Error::IntoStringError(ref e) => write!(f, "Failed to parse pw_dir as String: {}.", e),
-            Error::ParseError(ref e) => write!(f, "Error while parsing .trashrc: Line {} - {}.", e.1, e.0),
-            Error::NulError(ref e) => write!(f, "Interior null byte found when parsing aliases or exports, don't pull null bytes there: {}.", e),
-            Error::Utf8Error(ref e) => write!(f, "System username was invalid utf-8: {}", e),
-        }
-    }
-}
+use crate::complete::{self, Spec};
+use crate::error::Error;
+use crate::expand;
+use crate::lexer::{self, Token};
+use crate::shell::Options;
 
 enum ParserState {
     LookingForSection,
-    PathSection,
-    ExportsSection,
-    AliasesSection,
+    Path,
+    Exports,
+    Aliases,
+    Options,
+    Completions,
+}
+
+pub struct Settings {
+    pub path: Vec<PathBuf>,
+    /// Names and values from the EXPORTS section
+    pub exports: Vec<(String, String)>,
+    pub aliases: HashMap<String, String>,
+    pub options: Options,
+    pub completions: HashMap<String, Spec>,
 }
 
 /// Loads the .trashrc in the user's home directory
-pub fn load_settings(home_dir: &str) -> Result<(Vec<PathBuf>, Vec<CString>, HashMap<CString, String>), Error> {
-    let mut exports: Vec<CString> = Vec::with_capacity(16);
+pub fn load_settings(home_dir: &str) -> Result<Settings, Error> {
+    let mut exports: Vec<(String, String)> = Vec::with_capacity(16);
     let mut path: Vec<PathBuf> = Vec::with_capacity(16);
-    let mut aliases: HashMap<CString, String> = HashMap::with_capacity(16);
+    let mut aliases: HashMap<String, String> = HashMap::with_capacity(16);
+    let mut options = Options::default();
+    let mut completions: HashMap<String, Spec> = HashMap::new();
 
     let mut trash_rc_path = PathBuf::from(home_dir);
     trash_rc_path.push(".trashrc");
@@ -74,6 +47,8 @@ pub fn load_settings(home_dir: &str) -> Result<(Vec<PathBuf>, Vec<CString>, Hash
         let mut visited_path = false;
         let mut visited_exports = false;
         let mut visited_aliases = false;
+        let mut visited_options = false;
+        let mut visited_completions = false;
         let mut expected_open = false;
 
         let mut line_number = 0;
@@ -91,92 +66,147 @@ pub fn load_settings(home_dir: &str) -> Result<(Vec<PathBuf>, Vec<CString>, Hash
                     } else {
                         let issue = match parser_state {
                             ParserState::LookingForSection => unreachable!(),
-                            ParserState::PathSection => "PATH section identifier was not immediately proceeded by an opening section token `{`",
-                            ParserState::ExportsSection => "EXPORTS section identifier was not immediately proceeded by an opening section token `{`",
-                            ParserState::AliasesSection => "ALIASES section identifier was not immediately proceeded by an opening section token `{`",
+                            ParserState::Path => "PATH section identifier was not immediately proceeded by an opening section token `{`",
+                            ParserState::Exports => "EXPORTS section identifier was not immediately proceeded by an opening section token `{`",
+                            ParserState::Aliases => "ALIASES section identifier was not immediately proceeded by an opening section token `{`",
+                            ParserState::Options => "OPTIONS section identifier was not immediately proceeded by an opening section token `{`",
+                            ParserState::Completions => "COMPLETIONS section identifier was not immediately proceeded by an opening section token `{`",
                         };
-                        return Err(Error::ParseError((issue.into(), line_number)));
+                        return Err(Error::Parse((issue.into(), line_number)));
                     }
                 }
                 match token {
                     "PATH" => {
                         if visited_path {
-                            return Err(Error::ParseError(("Encountered PATH identifier but PATH already set".into(), line_number)));
+                            return Err(Error::Parse(("Encountered PATH identifier but PATH already set".into(), line_number)));
                         }
                         match parser_state {
                             ParserState::LookingForSection => (),
-                            ParserState::PathSection => return Err(Error::ParseError(("Encountered PATH section identifier while still processing PATH".into(), line_number))),
-                            ParserState::ExportsSection => return Err(Error::ParseError(("Encountered PATH section identifier while still processing EXPORTS".into(), line_number))),
-                            ParserState::AliasesSection => return Err(Error::ParseError(("Encountered ALIASES section identifier while still processing ALIASES".into(), line_number))),
+                            ParserState::Path => return Err(Error::Parse(("Encountered PATH section identifier while still processing PATH".into(), line_number))),
+                            ParserState::Exports => return Err(Error::Parse(("Encountered PATH section identifier while still processing EXPORTS".into(), line_number))),
+                            ParserState::Aliases => return Err(Error::Parse(("Encountered ALIASES section identifier while still processing ALIASES".into(), line_number))),
+                            ParserState::Options => return Err(Error::Parse(("Encountered PATH section identifier while still processing OPTIONS".into(), line_number))),
+                            ParserState::Completions => return Err(Error::Parse(("Encountered PATH section identifier while still processing COMPLETIONS".into(), line_number))),
                         }
                         expected_open = true;
-                        parser_state = ParserState::PathSection;
+                        parser_state = ParserState::Path;
                     },
                     "EXPORTS" => {
                         if visited_exports {
-                            return Err(Error::ParseError(("Encountered EXPORTS identifier but EXPORTS already set".into(), line_number)));
+                            return Err(Error::Parse(("Encountered EXPORTS identifier but EXPORTS already set".into(), line_number)));
                         }
                         match parser_state {
                             ParserState::LookingForSection => (),
-                            ParserState::PathSection => return Err(Error::ParseError(("Encountered EXPORTS identifier while still processing PATH".into(), line_number))),
-                            ParserState::ExportsSection => return Err(Error::ParseError(("Encountered EXPORTS identifier while still processing EXPORTS".into(), line_number))),
-                            ParserState::AliasesSection => return Err(Error::ParseError(("Encountered ALIASES identifier while still processing ALIASES".into(), line_number))),
+                            ParserState::Path => return Err(Error::Parse(("Encountered EXPORTS identifier while still processing PATH".into(), line_number))),
+                            ParserState::Exports => return Err(Error::Parse(("Encountered EXPORTS identifier while still processing EXPORTS".into(), line_number))),
+                            ParserState::Aliases => return Err(Error::Parse(("Encountered ALIASES identifier while still processing ALIASES".into(), line_number))),
+                            ParserState::Options => return Err(Error::Parse(("Encountered EXPORTS identifier while still processing OPTIONS".into(), line_number))),
+                            ParserState::Completions => return Err(Error::Parse(("Encountered EXPORTS identifier while still processing COMPLETIONS".into(), line_number))),
                         }
                         expected_open = true;
-                        parser_state = ParserState::ExportsSection;
+                        parser_state = ParserState::Exports;
                     },
                     "ALIASES" => {
                         if visited_aliases {
-                            return Err(Error::ParseError(("Encountered ALIASES identifier but ALIASES already set.".into(), line_number)));
+                            return Err(Error::Parse(("Encountered ALIASES identifier but ALIASES already set.".into(), line_number)));
                         }
                         expected_open = true;
-                        parser_state = ParserState::AliasesSection;
+                        parser_state = ParserState::Aliases;
+                    },
+                    "OPTIONS" => {
+                        if visited_options {
+                            return Err(Error::Parse(("Encountered OPTIONS identifier but OPTIONS already set".into(), line_number)));
+                        }
+                        match parser_state {
+                            ParserState::LookingForSection => (),
+                            _ => return Err(Error::Parse(("Encountered OPTIONS identifier while still processing another section".into(), line_number))),
+                        }
+                        expected_open = true;
+                        parser_state = ParserState::Options;
+                    },
+                    "COMPLETIONS" => {
+                        if visited_completions {
+                            return Err(Error::Parse(("Encountered COMPLETIONS identifier but COMPLETIONS already set".into(), line_number)));
+                        }
+                        match parser_state {
+                            ParserState::LookingForSection => (),
+                            _ => return Err(Error::Parse(("Encountered COMPLETIONS identifier while still processing another section".into(), line_number))),
+                        }
+                        expected_open = true;
+                        parser_state = ParserState::Completions;
                     },
                     "}" => {
                         match parser_state {
-                            ParserState::LookingForSection => return Err(Error::ParseError(("Encountered closing section token `}` but no section was open".into(), line_number))),
-                            ParserState::ExportsSection => {
+                            ParserState::LookingForSection => return Err(Error::Parse(("Encountered closing section token `}` but no section was open".into(), line_number))),
+                            ParserState::Exports => {
                                 visited_exports = true
                             },
-                            ParserState::PathSection => {
+                            ParserState::Path => {
                                 visited_path = true
                             },
-                            ParserState::AliasesSection =>{
+                            ParserState::Aliases =>{
                                 visited_aliases = true
                             },
+                            ParserState::Options => {
+                                visited_options = true
+                            },
+                            ParserState::Completions => {
+                                visited_completions = true
+                            },
                         }
                         parser_state = ParserState::LookingForSection;
                     },
                     "{" => {
-                        return Err(Error::ParseError(("Received opening section token `{` but without a preceding identifier".into(), line_number)));
+                        return Err(Error::Parse(("Received opening section token `{` but without a preceding identifier".into(), line_number)));
                     },
                     _ => {
                         match parser_state {
                             ParserState::LookingForSection => {
-                                return Err(Error::ParseError((format!("Encountered unexpected token `{}`; expected section identifier", token), line_number)));
+                                return Err(Error::Parse((format!("Encountered unexpected token `{}`; expected section identifier", token), line_number)));
                             },
-                            ParserState::PathSection => {
-                                path.push(PathBuf::from(token))
+                            ParserState::Path => {
+                                path.push(PathBuf::from(expand::expand_home(token, home_dir)))
                             },
-                            ParserState::ExportsSection => {
-                                let bytes: Vec<u8> = token.bytes().collect();
-                                exports.push(CString::new(bytes)?);
+                            ParserState::Exports => {
+                                match token.split_once('=') {
+                                    Some((name, value)) => exports.push((name.to_string(), value.to_string())),
+                                    None => return Err(Error::Parse((format!("Failed to create export from `{}`, expected NAME=value", token), line_number))),
+                                }
                             },
-                            ParserState::AliasesSection => {
-                                let alias: Vec<&str> = line.trim().splitn(2, |x| x == '=').collect();
+                            ParserState::Aliases => {
+                                let alias: Vec<&str> = line.trim().splitn(2, '=').collect();
                                 if alias.len() != 2 {
-                                    return Err(Error::ParseError((format!("Failed to create alias from `{}`", line.trim()), line_number)));
+                                    return Err(Error::Parse((format!("Failed to create alias from `{}`", line.trim()), line_number)));
                                 }
-                                let mut replacement = String::from(alias[1]);
-                                unsafe {
-                                    for byte in replacement.as_bytes_mut() {
-                                        if *byte == b' ' {
-                                            *byte = 0;
-                                        }
+                                aliases.insert(alias[0].to_string(), alias[1].to_string());
+                                break
+                            },
+                            ParserState::Options => {
+                                // `name` turns an option on, `noname` turns it off
+                                let (name, enable) = match token.strip_prefix("no") {
+                                    Some(name) if options.get(name).is_some() => (name, false),
+                                    _ => (token, true),
+                                };
+                                match options.get_mut(name) {
+                                    Some(option) => *option = enable,
+                                    None => return Err(Error::Parse((format!("Unknown option `{}`", token), line_number))),
+                                }
+                            },
+                            ParserState::Completions => {
+                                // Each line holds the same arguments the `complete` builtin takes
+                                let tokens = lexer::tokenize(line.trim()).map_err(|e| Error::Parse((e.to_string(), line_number)))?;
+                                let mut args = Vec::with_capacity(tokens.len());
+                                for token in tokens {
+                                    match token {
+                                        Token::Word(word) => args.push(word.text()),
+                                        Token::Newline => (),
+                                        token => return Err(Error::Parse((format!("Unexpected `{}` in completion spec", token.describe()), line_number))),
                                     }
                                 }
-                                replacement.push('\0');
-                                aliases.insert(CString::new(alias[0])?, replacement);
+                                let (spec, names) = complete::parse_spec(&args).map_err(|e| Error::Parse((e, line_number)))?;
+                                for name in names {
+                                    completions.insert(name, spec.clone());
+                                }
                                 break
                             },
                         }
@@ -187,35 +217,23 @@ pub fn load_settings(home_dir: &str) -> Result<(Vec<PathBuf>, Vec<CString>, Hash
 
         match parser_state {
             ParserState::LookingForSection => (),
-            ParserState::PathSection => return Err(Error::ParseError(("Still parsing PATH section when end of .trashrc was reached".into(), line_number))),
-            ParserState::ExportsSection => return Err(Error::ParseError(("Still parsing EXPORTS section when end of .trashrc was reached".into(), line_number))),
-            ParserState::AliasesSection => return Err(Error::ParseError(("Still parsing ALIASES section when end of .trashrc was reached".into(), line_number)))
+            ParserState::Path => return Err(Error::Parse(("Still parsing PATH section when end of .trashrc was reached".into(), line_number))),
+            ParserState::Exports => return Err(Error::Parse(("Still parsing EXPORTS section when end of .trashrc was reached".into(), line_number))),
+            ParserState::Aliases => return Err(Error::Parse(("Still parsing ALIASES section when end of .trashrc was reached".into(), line_number))),
+            ParserState::Options => return Err(Error::Parse(("Still parsing OPTIONS section when end of .trashrc was reached".into(), line_number))),
+            ParserState::Completions => return Err(Error::Parse(("Still parsing COMPLETIONS section when end of .trashrc was reached".into(), line_number))),
         }
     }
 
-    // If a PATH is already set, append those values
+    // If a PATH is already set, append those values, skipping any we already have since they'd only slow down lookups
     if let Ok(path_string) = env::var("PATH") {
-        for segment in path_string.split(":") {
-            path.push(PathBuf::from(segment));
-        }
-    }
-
-    // export our PATH
-    {
-        let mut path_string = path.iter().fold(String::from("PATH="), |mut string, path| { string.push_str(path.to_str().unwrap()); string.push(':'); string });
-        if path_string.ends_with(":") {
-            let _ = path_string.pop(); // Remove trailing :
+        for segment in path_string.split(':') {
+            let segment = PathBuf::from(segment);
+            if !path.contains(&segment) {
+                path.push(segment);
+            }
         }
-
-        exports.push(CString::new(path_string)?);
-    }
-
-    // export HOME
-    {
-        let home_string = format!("HOME={}", home_dir);
-
-        exports.push(CString::new(home_string)?);
     }
 
-    Ok((path, exports, aliases))
+    Ok(Settings { path, exports, aliases, options, completions })
 }
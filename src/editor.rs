@@ -0,0 +1,663 @@
+use std::io::{self, Write};
+use std::mem::{self, MaybeUninit};
+
+use crate::complete::Completion;
+use crate::history::Entry;
+
+/// How long to wait for the rest of an escape sequence before deciding escape was pressed on its own, in milliseconds
+const ESCAPE_TIMEOUT: i32 = 50;
+const KILL_RING_SIZE: usize = 16;
+
+enum Key {
+    Char(char),
+    /// Holds the lowercase letter
+    Ctrl(u8),
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    AltBackspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    Escape,
+    Unknown,
+}
+
+#[derive(Default, PartialEq)]
+enum Action {
+    Kill,
+    Yank,
+    #[default]
+    Other,
+}
+
+struct Search {
+    query: String,
+    failing: bool,
+    /// The history entry currently shown, or the length of the history if there's no match yet
+    index: usize,
+    original: Vec<char>,
+    original_cursor: usize,
+}
+
+/// Puts the terminal in raw mode, and puts it back how it was when dropped
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        unsafe {
+            let mut original: MaybeUninit<libc::termios> = MaybeUninit::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = original.assume_init();
+
+            // Output processing stays on so that "\n" still means a new line
+            let mut raw = original;
+            raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+            raw.c_cflag |= libc::CS8;
+            raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(RawMode { original })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+/// Whether we're talking to a terminal, and so should edit lines ourselves
+pub fn is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Reads one byte from the terminal, None on EOF or if a timeout is given and nothing arrives in time
+fn read_byte(timeout: Option<i32>) -> io::Result<Option<u8>> {
+    if let Some(timeout) = timeout {
+        let mut poll_fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut poll_fd, 1, timeout) } <= 0 {
+            return Ok(None);
+        }
+    }
+
+    let mut byte = 0u8;
+    loop {
+        match unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Reads the rest of a utf-8 character starting with `first`
+fn read_char(first: u8) -> io::Result<Option<char>> {
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(None),
+    };
+
+    let mut bytes = vec![first];
+    while bytes.len() < len {
+        match read_byte(None)? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()))
+}
+
+/// Reads whatever followed an escape: an arrow key or similar, a meta key, or nothing at all
+fn read_escape() -> io::Result<Key> {
+    let byte = match read_byte(Some(ESCAPE_TIMEOUT))? {
+        Some(byte) => byte,
+        None => return Ok(Key::Escape),
+    };
+
+    match byte {
+        b'[' | b'O' => {
+            let mut params = Vec::new();
+            let final_byte = loop {
+                match read_byte(Some(ESCAPE_TIMEOUT))? {
+                    Some(byte @ 0x40..=0x7e) => break byte,
+                    Some(byte) => params.push(byte),
+                    None => return Ok(Key::Unknown),
+                }
+            };
+
+            Ok(match (params.as_slice(), final_byte) {
+                (b"", b'A') => Key::Up,
+                (b"", b'B') => Key::Down,
+                (b"", b'C') => Key::Right,
+                (b"", b'D') => Key::Left,
+                (b"", b'H') | (b"1", b'~') | (b"7", b'~') => Key::Home,
+                (b"", b'F') | (b"4", b'~') | (b"8", b'~') => Key::End,
+                (b"3", b'~') => Key::Delete,
+                (b"1;5", b'C') | (b"1;3", b'C') => Key::WordRight,
+                (b"1;5", b'D') | (b"1;3", b'D') => Key::WordLeft,
+                _ => Key::Unknown,
+            })
+        },
+        0x7f | 0x08 => Ok(Key::AltBackspace),
+        _ => Ok(match read_char(byte)? {
+            Some(c) => Key::Alt(c),
+            None => Key::Unknown,
+        }),
+    }
+}
+
+/// The next key pressed, or None on EOF
+fn read_key() -> io::Result<Option<Key>> {
+    let byte = match read_byte(None)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => read_escape()?,
+        0x01..=0x1a => Key::Ctrl(byte - 1 + b'a'),
+        0x00..=0x1f => Key::Unknown,
+        _ => match read_char(byte)? {
+            Some(c) => Key::Char(c),
+            None => Key::Unknown,
+        },
+    };
+
+    Ok(Some(key))
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { MaybeUninit::zeroed().assume_init() };
+    let ret_val = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret_val == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+/// The number of columns text takes up on screen, not counting color escapes
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else if !c.is_control() {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// The longest prefix all the strings share
+fn common_prefix(strings: &[String]) -> String {
+    let mut prefix: &str = &strings[0];
+    for string in strings[1..].iter() {
+        let len = prefix.chars().zip(string.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+        prefix = &prefix[..len];
+    }
+    prefix.to_string()
+}
+
+/// Just the file name of a path candidate, keeping the `/` that marks a directory
+fn display_name(candidate: &str) -> String {
+    let trimmed = candidate.trim_end_matches('/');
+    let name = trimmed.rsplit('/').next().unwrap_or(trimmed);
+    if candidate.ends_with('/') && !trimmed.is_empty() {
+        format!("{}/", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+fn is_not_space(c: char) -> bool {
+    !c.is_whitespace()
+}
+
+/// Emacs style line editing on a terminal in raw mode
+#[derive(Default)]
+pub struct Editor {
+    prompt: String,
+    prompt_width: usize,
+    buffer: Vec<char>,
+    cursor: usize,
+    /// How many rows below the start of the prompt the terminal's cursor is
+    cursor_row: usize,
+    /// Which history entry is being shown, the length of the history means the line being typed
+    history_index: usize,
+    /// The line being typed, kept while moving through history
+    draft: Vec<char>,
+    search: Option<Search>,
+    kill_ring: Vec<String>,
+    /// Where the last yank was inserted, and which kill ring entry it was
+    yanked: (usize, usize, usize),
+    last_action: Action,
+    previous_action: Action,
+}
+
+impl Editor {
+    /// Shows the prompt and reads a line into `line`, like `io::Stdin::read_line`. Returns false on EOF.
+    /// `complete` is given the text before the cursor when Tab is pressed
+    pub fn read_line(&mut self, line: &mut String, prompt: &str, history: &[Entry], complete: &mut dyn FnMut(&[char]) -> Completion) -> io::Result<bool> {
+        let _raw_mode = RawMode::enable()?;
+
+        self.prompt = prompt.to_string();
+        self.prompt_width = display_width(prompt);
+        self.buffer.clear();
+        self.cursor = 0;
+        self.cursor_row = 0;
+        self.history_index = history.len();
+        self.draft.clear();
+        self.search = None;
+        self.last_action = Action::Other;
+        self.refresh()?;
+
+        loop {
+            let key = match read_key()? {
+                Some(key) => key,
+                None if self.buffer.is_empty() => return Ok(false),
+                None => Key::Enter,
+            };
+
+            self.previous_action = mem::replace(&mut self.last_action, Action::Other);
+
+            let key = if self.search.is_some() {
+                match self.search_key(key, history) {
+                    Some(key) => key,
+                    None => {
+                        self.refresh()?;
+                        continue;
+                    }
+                }
+            } else {
+                key
+            };
+
+            match key {
+                Key::Enter => {
+                    self.cursor = self.buffer.len();
+                    self.refresh()?;
+                    self.write(b"\n")?;
+                    line.extend(self.buffer.iter());
+                    line.push('\n');
+                    return Ok(true);
+                },
+                Key::Ctrl(b'd') if self.buffer.is_empty() => {
+                    self.write(b"\n")?;
+                    return Ok(false);
+                },
+                Key::Ctrl(b'c') => {
+                    // Abandon the line, leaving it on screen
+                    self.cursor = self.buffer.len();
+                    self.refresh()?;
+                    self.write(b"^C\n")?;
+                    line.push('\n');
+                    return Ok(true);
+                },
+                Key::Char(c) => {
+                    self.buffer.insert(self.cursor, c);
+                    self.cursor += 1;
+                },
+                Key::Ctrl(b'a') | Key::Home => self.cursor = 0,
+                Key::Ctrl(b'e') | Key::End => self.cursor = self.buffer.len(),
+                Key::Ctrl(b'b') | Key::Left => self.cursor = self.cursor.saturating_sub(1),
+                Key::Ctrl(b'f') | Key::Right => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+                Key::Alt('b') | Key::WordLeft => self.cursor = self.boundary_before(self.cursor, is_word),
+                Key::Alt('f') | Key::WordRight => self.cursor = self.boundary_after(self.cursor, is_word),
+                Key::Backspace => {
+                    if self.cursor > 0 {
+                        self.cursor -= 1;
+                        self.buffer.remove(self.cursor);
+                    }
+                },
+                Key::Ctrl(b'd') | Key::Delete => {
+                    if self.cursor < self.buffer.len() {
+                        self.buffer.remove(self.cursor);
+                    }
+                },
+                Key::Tab => self.complete(complete)?,
+                Key::Ctrl(b'k') => self.kill(self.cursor, self.buffer.len(), false),
+                Key::Ctrl(b'u') => self.kill(0, self.cursor, true),
+                Key::Ctrl(b'w') => self.kill(self.boundary_before(self.cursor, is_not_space), self.cursor, true),
+                Key::AltBackspace => self.kill(self.boundary_before(self.cursor, is_word), self.cursor, true),
+                Key::Alt('d') => self.kill(self.cursor, self.boundary_after(self.cursor, is_word), false),
+                Key::Ctrl(b'y') => self.yank(),
+                Key::Alt('y') => self.yank_pop(),
+                Key::Ctrl(b't') => self.transpose(),
+                Key::Ctrl(b'l') => {
+                    self.write(b"\x1b[H\x1b[2J")?;
+                    self.cursor_row = 0;
+                },
+                Key::Ctrl(b'p') | Key::Up => self.history_move(history, true),
+                Key::Ctrl(b'n') | Key::Down => self.history_move(history, false),
+                Key::Ctrl(b'r') => {
+                    if self.history_index == history.len() {
+                        self.draft = self.buffer.clone();
+                    }
+                    self.search = Some(Search {
+                        query: String::new(),
+                        failing: false,
+                        index: history.len(),
+                        original: self.buffer.clone(),
+                        original_cursor: self.cursor,
+                    });
+                },
+                _ => (),
+            }
+
+            self.refresh()?;
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+
+    /// Redraws the prompt and line, and puts the cursor where it belongs
+    fn refresh(&mut self) -> io::Result<()> {
+        let (prompt, prompt_width) = match self.search {
+            Some(ref search) => {
+                let prompt = format!("({}reverse-i-search)`{}': ", if search.failing { "failed " } else { "" }, search.query);
+                let width = display_width(&prompt);
+                (prompt, width)
+            },
+            None => (self.prompt.clone(), self.prompt_width),
+        };
+        let columns = terminal_width();
+        let mut out = Vec::new();
+
+        // Go back to where the prompt starts and clear everything drawn last time
+        if self.cursor_row > 0 {
+            write!(out, "\x1b[{}A", self.cursor_row)?;
+        }
+        out.extend_from_slice(b"\r\x1b[J");
+        out.extend_from_slice(prompt.as_bytes());
+        out.extend_from_slice(self.buffer.iter().collect::<String>().as_bytes());
+
+        // Terminals don't wrap until the next character is written, so wrap ourselves to keep the cursor where we expect
+        let end = prompt_width + self.buffer.len();
+        if end > 0 && end.is_multiple_of(columns) {
+            out.extend_from_slice(b"\r\n");
+        }
+
+        let position = prompt_width + self.cursor;
+        let (row, column) = (position / columns, position % columns);
+        if end / columns > row {
+            write!(out, "\x1b[{}A", end / columns - row)?;
+        }
+        out.push(b'\r');
+        if column > 0 {
+            write!(out, "\x1b[{}C", column)?;
+        }
+        self.cursor_row = row;
+
+        self.write(&out)
+    }
+
+    /// Completes the word before the cursor as far as it can, listing the candidates if it can't go any further
+    fn complete(&mut self, complete: &mut dyn FnMut(&[char]) -> Completion) -> io::Result<()> {
+        let completion = complete(&self.buffer[..self.cursor]);
+        let replacement = match completion.candidates.len() {
+            0 => return self.write(b"\x07"),
+            1 => {
+                let mut replacement = completion.candidates[0].clone();
+                if !replacement.ends_with('/') {
+                    replacement.push(' ');
+                }
+                replacement
+            },
+            _ => {
+                let prefix = common_prefix(&completion.candidates);
+                if prefix.chars().count() <= self.cursor - completion.start {
+                    return self.list_candidates(&completion.candidates);
+                }
+                prefix
+            },
+        };
+
+        let replacement: Vec<char> = replacement.chars().collect();
+        self.buffer.splice(completion.start..self.cursor, replacement.iter().cloned());
+        self.cursor = completion.start + replacement.len();
+        Ok(())
+    }
+
+    /// Lists candidates in columns below the line, the prompt is redrawn below them afterwards
+    fn list_candidates(&mut self, candidates: &[String]) -> io::Result<()> {
+        let names: Vec<String> = candidates.iter().map(|candidate| display_name(candidate)).collect();
+        let width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0) + 2;
+        let columns = (terminal_width() / width).max(1);
+        let rows = names.len().div_ceil(columns);
+
+        let cursor = self.cursor;
+        self.cursor = self.buffer.len();
+        self.refresh()?;
+        self.cursor = cursor;
+
+        let mut out = String::from("\n");
+        for row in 0..rows {
+            let mut line = String::new();
+            // Down the columns, like ls
+            for column in 0..columns {
+                if let Some(name) = names.get(column * rows + row) {
+                    line.push_str(&format!("{:<width$}", name, width = width));
+                }
+            }
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        self.write(out.as_bytes())?;
+        self.cursor_row = 0;
+        Ok(())
+    }
+
+    /// The start of the word before `from`, where words are made of characters matching `in_word`
+    fn boundary_before(&self, from: usize, in_word: fn(char) -> bool) -> usize {
+        let mut i = from;
+        while i > 0 && !in_word(self.buffer[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && in_word(self.buffer[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    /// The end of the word after `from`
+    fn boundary_after(&self, from: usize, in_word: fn(char) -> bool) -> usize {
+        let mut i = from;
+        while i < self.buffer.len() && !in_word(self.buffer[i]) {
+            i += 1;
+        }
+        while i < self.buffer.len() && in_word(self.buffer[i]) {
+            i += 1;
+        }
+        i
+    }
+
+    /// Removes the text between `start` and `end` into the kill ring. Kills in a row build up a single entry
+    fn kill(&mut self, start: usize, end: usize, backward: bool) {
+        if start >= end {
+            self.last_action = mem::replace(&mut self.previous_action, Action::Other);
+            return;
+        }
+
+        let text: String = self.buffer.drain(start..end).collect();
+        self.cursor = start;
+
+        match self.kill_ring.last_mut() {
+            Some(last) if self.previous_action == Action::Kill => {
+                if backward {
+                    last.insert_str(0, &text);
+                } else {
+                    last.push_str(&text);
+                }
+            },
+            _ => {
+                if self.kill_ring.len() == KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+                self.kill_ring.push(text);
+            },
+        }
+
+        self.last_action = Action::Kill;
+    }
+
+    fn insert_yank(&mut self, index: usize) {
+        let text: Vec<char> = self.kill_ring[index].chars().collect();
+        let start = self.cursor;
+        self.buffer.splice(start..start, text.iter().cloned());
+        self.cursor += text.len();
+        self.yanked = (start, text.len(), index);
+        self.last_action = Action::Yank;
+    }
+
+    fn yank(&mut self) {
+        if !self.kill_ring.is_empty() {
+            self.insert_yank(self.kill_ring.len() - 1);
+        }
+    }
+
+    /// Replaces what was just yanked with the kill before it
+    fn yank_pop(&mut self) {
+        if self.previous_action != Action::Yank {
+            return;
+        }
+
+        let (start, len, index) = self.yanked;
+        self.buffer.drain(start..start + len);
+        self.cursor = start;
+        self.insert_yank((index + self.kill_ring.len() - 1) % self.kill_ring.len());
+    }
+
+    /// Swaps the characters around the cursor, or the last two at the end of the line
+    fn transpose(&mut self) {
+        if self.buffer.len() < 2 || self.cursor == 0 {
+            return;
+        }
+
+        if self.cursor == self.buffer.len() {
+            self.cursor -= 1;
+        }
+        self.buffer.swap(self.cursor - 1, self.cursor);
+        self.cursor += 1;
+    }
+
+    fn history_move(&mut self, history: &[Entry], up: bool) {
+        if (up && self.history_index == 0) || (!up && self.history_index >= history.len()) {
+            return;
+        }
+
+        if self.history_index == history.len() {
+            self.draft = self.buffer.clone();
+        }
+
+        if up {
+            self.history_index -= 1;
+        } else {
+            self.history_index += 1;
+        }
+
+        self.buffer = match history.get(self.history_index) {
+            Some(entry) => entry.line.chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.cursor = self.buffer.len();
+    }
+
+    /// Looks for the query in history entries older than `before`, showing the newest match
+    fn search_history(&mut self, history: &[Entry], before: usize) {
+        let search = self.search.as_mut().unwrap();
+        if search.query.is_empty() {
+            search.failing = false;
+            return;
+        }
+
+        match history[..before].iter().rposition(|entry| entry.line.contains(&search.query)) {
+            Some(index) => {
+                let entry = &history[index].line;
+                search.failing = false;
+                search.index = index;
+                self.buffer = entry.chars().collect();
+                self.cursor = entry[..entry.find(&search.query).unwrap()].chars().count();
+                self.history_index = index;
+            },
+            None => search.failing = true,
+        }
+    }
+
+    /// Handles a key during a reverse search. Keys that end the search are handed back to be handled as usual
+    fn search_key(&mut self, key: Key, history: &[Entry]) -> Option<Key> {
+        let search = self.search.as_mut().unwrap();
+        match key {
+            Key::Ctrl(b'r') => {
+                let before = search.index;
+                self.search_history(history, before);
+            },
+            Key::Char(c) => {
+                search.query.push(c);
+                // The current match may still match with the longer query
+                let before = (search.index + 1).min(history.len());
+                self.search_history(history, before);
+            },
+            Key::Backspace => {
+                search.query.pop();
+                self.search_history(history, history.len());
+            },
+            Key::Ctrl(b'g') | Key::Escape => {
+                let search = self.search.take().unwrap();
+                self.buffer = search.original;
+                self.cursor = search.original_cursor;
+                self.history_index = history.len();
+            },
+            key => {
+                self.search = None;
+                return Some(key);
+            },
+        }
+
+        None
+    }
+}
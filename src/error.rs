@@ -0,0 +1,107 @@
+use std::convert::From;
+use std::ffi::{self, CStr};
+use std::io;
+
+use crate::parser;
+
+pub enum Error {
+    // Loading .trashrc
+    Io(io::Error),
+    Utf8(std::str::Utf8Error),
+    IntoString(ffi::IntoStringError),
+    Parse((String, usize)),
+
+    // Running a command, these hold the errno where there is one
+    Spawn(String, i32),
+    CommandNotFound(String),
+    NotExecutable(String),
+    Fork(i32),
+    Pipe(i32),
+    Wait(libc::pid_t, i32),
+    Redirect(String, i32),
+    AmbiguousRedirect(String),
+    /// The target of a redirection expanded to more or less than one word
+    RedirectExpansion(String),
+    NulInArgument,
+    ParameterUnset(String, String),
+    /// Holds the expression and what went wrong
+    Arithmetic(String, String),
+    /// In the commands of a command substitution
+    Syntax(parser::Error),
+    /// A pattern matched no files with failglob on
+    NoMatch(String),
+
+    // History expansion
+    HistoryEvent(String),
+    HistorySubstitution(String),
+}
+
+impl Error {
+    /// The exit status a command reports when it fails with this error
+    pub fn exit_status(&self) -> i32 {
+        match *self {
+            Error::CommandNotFound(_) => 127,
+            Error::NotExecutable(_) => 126,
+            _ => 1,
+        }
+    }
+}
+
+/// The description of an errno, without the "(os error N)" that io::Error tacks on
+pub fn errno_description(errno: i32) -> String {
+    unsafe { CStr::from_ptr(libc::strerror(errno)).to_string_lossy().into_owned() }
+}
+
+pub fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ffi::IntoStringError> for Error {
+    fn from(e: ffi::IntoStringError) -> Error {
+        Error::IntoString(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Error {
+        Error::Utf8(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match *self {
+            Error::Io(ref e) => write!(f, "Encountered I/O error while attempting to load .trashrc: {}.", e),
+            Error::IntoString(ref e) => write!(f, "Failed to parse pw_dir as String: {}.", e),
+            Error::Parse(ref e) => write!(f, "Error while parsing .trashrc: Line {} - {}.", e.1, e.0),
+            Error::Utf8(ref e) => write!(f, "System username was invalid utf-8: {}", e),
+            Error::Spawn(ref name, libc::EAGAIN) => write!(f, "{}: Can't allocate resources to spawn child process", name),
+            Error::Spawn(ref name, libc::ENOMEM) => write!(f, "{}: Can't allocate memory to spawn child process", name),
+            Error::Spawn(_, libc::ENOSYS) => write!(f, "posix_spawn unsupported on this platform"),
+            Error::Spawn(ref name, errno) => write!(f, "{}: Error occurred while trying to spawn child process: {}", name, errno_description(errno)),
+            Error::CommandNotFound(ref name) if name.contains('/') => write!(f, "{}: No such file or directory", name),
+            Error::CommandNotFound(ref name) => write!(f, "Command not found {:?}.", name),
+            Error::NotExecutable(ref path) => write!(f, "Found {}, but couldn't execute it: Permission denied", path),
+            Error::Fork(errno) => write!(f, "Failed to fork: {}", errno_description(errno)),
+            Error::Pipe(errno) => write!(f, "Failed to create pipe: {}", errno_description(errno)),
+            Error::Wait(pid, libc::ECHILD) => write!(f, "Somehow, no child process {} to wait for", pid),
+            Error::Wait(pid, errno) => write!(f, "Error occurred while trying to wait for child process {}: {}", pid, errno_description(errno)),
+            Error::Redirect(ref path, errno) => write!(f, "{}: {}", path, errno_description(errno)),
+            Error::AmbiguousRedirect(ref target) => write!(f, "{}: ambiguous redirect, expected a file descriptor", target),
+            Error::RedirectExpansion(ref target) => write!(f, "{}: ambiguous redirect", target),
+            Error::ParameterUnset(ref name, ref message) => write!(f, "{}: {}", name, message),
+            Error::Arithmetic(ref expression, ref message) => write!(f, "{}: {}", expression, message),
+            Error::Syntax(ref e) => write!(f, "{}", e),
+            Error::NoMatch(ref pattern) => write!(f, "no match: {}", pattern),
+            Error::NulInArgument => write!(f, "Arguments and file names can't contain null bytes"),
+            Error::HistoryEvent(ref event) => write!(f, "{}: event not found", event),
+            Error::HistorySubstitution(ref substitution) => write!(f, "{}: substitution failed", substitution),
+        }
+    }
+}
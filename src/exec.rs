@@ -0,0 +1,517 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+
+use crate::builtins;
+use crate::error::{self, Error};
+use crate::expand;
+use crate::jobs::{self, Job, Process, ProcessState};
+use crate::path::Resolution;
+use crate::parser::{self, AndOr, Command, Connector, List, Pipeline, Redirect, RedirectKind};
+use crate::shell::Shell;
+
+pub fn run_list(shell: &mut Shell, list: &List) {
+    for and_or in list.and_ors.iter() {
+        if and_or.background {
+            run_background(shell, and_or);
+        } else {
+            run_and_or(shell, and_or);
+        }
+    }
+}
+
+/// Starts the and-or list as a job, and returns right away
+fn run_background(shell: &mut Shell, and_or: &AndOr) {
+    let job = if and_or.rest.is_empty() {
+        start_pipeline(shell, &and_or.first, None)
+    } else {
+        // Anything more than a single pipeline gets a copy of the shell to run it
+        let mut job = Job::new(and_or.to_string());
+        let pid = fork_child(shell, 0);
+        if pid == -1 {
+            let e = Error::Fork(error::errno());
+            eprintln!("trash: {}", e);
+            shell.exit_status = e.exit_status();
+            return;
+        } else if pid == 0 {
+            run_and_or(shell, and_or);
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(shell.exit_status) };
+        }
+        job.processes.push(Process { pid, state: ProcessState::Running });
+        if shell.job_control {
+            job.pgid = pid;
+        }
+        job
+    };
+
+    let last_pid = job.processes.iter().rev().find(|process| process.pid != 0).map(|process| process.pid);
+    let id = shell.jobs.add(job);
+    if let Some(pid) = last_pid {
+        // Only worth announcing to someone at a prompt, not in a script
+        if shell.job_control {
+            eprintln!("[{}] {}", id, pid);
+        }
+        shell.last_background_pid = Some(pid);
+    }
+    shell.exit_status = 0;
+    shell.exit_signal = None;
+}
+
+/// Runs the commands of a command substitution in a copy of the shell, returning what they wrote to stdout.
+/// The exit status is left in `shell.exit_status`
+pub fn substitute(shell: &mut Shell, source: &str) -> Result<String, Error> {
+    let list = parser::parse(source, &shell.aliases).map_err(Error::Syntax)?;
+
+    let mut fds: [libc::c_int; 2] = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(Error::Pipe(error::errno()));
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    // Otherwise whatever's buffered would be written twice
+    let _ = io::stdout().flush();
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+        return Err(Error::Fork(error::errno()));
+    } else if pid == 0 {
+        // The copy stays in the shell's process group, so what it runs can still use the terminal
+        unsafe {
+            libc::dup2(write_fd, libc::STDOUT_FILENO);
+            if shell.job_control {
+                for &signal in jobs::JOB_CONTROL_SIGNALS {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+            }
+            libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        }
+        shell.job_control = false;
+        run_list(shell, &list);
+        let _ = io::stdout().flush();
+        unsafe { libc::_exit(shell.exit_status) };
+    }
+
+    unsafe { libc::close(write_fd) };
+    let mut output = Vec::new();
+    let result = unsafe { File::from_raw_fd(read_fd) }.read_to_end(&mut output);
+
+    let mut job = Job::new(source.to_string());
+    job.processes.push(Process { pid, state: ProcessState::Running });
+    jobs::wait_for(&mut job);
+    shell.exit_status = job.status(false);
+    shell.exit_signal = None;
+
+    result?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+fn run_and_or(shell: &mut Shell, and_or: &AndOr) {
+    run_pipeline(shell, &and_or.first);
+
+    for (connector, pipeline) in and_or.rest.iter() {
+        let should_run = match connector {
+            Connector::And => shell.exit_status == 0,
+            Connector::Or => shell.exit_status != 0,
+        };
+
+        if should_run {
+            run_pipeline(shell, pipeline);
+        }
+    }
+}
+
+/// A change to a child's file descriptors, applied in order before it runs
+#[derive(Clone, Copy)]
+enum FdAction {
+    Dup { from: libc::c_int, to: libc::c_int },
+    Close(libc::c_int),
+}
+
+/// The file descriptor changes for one command, along with the files that were opened to make them
+#[derive(Default)]
+struct Redirections {
+    actions: Vec<FdAction>,
+    opened: Vec<libc::c_int>,
+    /// Held by the shell for a later stage, like the read end of the next pipe. Not closed on drop
+    held: Vec<libc::c_int>,
+}
+
+impl Drop for Redirections {
+    fn drop(&mut self) {
+        for &fd in self.opened.iter() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+impl Redirections {
+    fn open(&mut self, path: &str, flags: libc::c_int) -> Result<libc::c_int, Error> {
+        let c_path = CString::new(path).map_err(|_| Error::NulInArgument)?;
+
+        let fd = unsafe { libc::open(c_path.as_ptr(), flags | libc::O_CLOEXEC, 0o666) };
+        if fd == -1 {
+            return Err(Error::Redirect(path.to_string(), error::errno()));
+        }
+
+        // Kept out of the way of fds the user can name, or a later redirect in the same command could clobber it
+        let moved_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+        let errno = error::errno();
+        unsafe { libc::close(fd) };
+        if moved_fd == -1 {
+            return Err(Error::Redirect(path.to_string(), errno));
+        }
+        let fd = moved_fd;
+
+        self.opened.push(fd);
+        Ok(fd)
+    }
+
+    /// Adds a redirection, given what its target expanded to
+    fn add(&mut self, redirect: &Redirect, target: String) -> Result<(), Error> {
+        let write_flags = libc::O_WRONLY | libc::O_CREAT;
+
+        match redirect.kind {
+            RedirectKind::Read => {
+                let fd = self.open(&target, libc::O_RDONLY)?;
+                self.actions.push(FdAction::Dup { from: fd, to: redirect.fd });
+            },
+            RedirectKind::Write | RedirectKind::Append => {
+                let flags = if let RedirectKind::Append = redirect.kind { libc::O_APPEND } else { libc::O_TRUNC };
+                let fd = self.open(&target, write_flags | flags)?;
+                self.actions.push(FdAction::Dup { from: fd, to: redirect.fd });
+            },
+            RedirectKind::WriteAll | RedirectKind::AppendAll => {
+                let flags = if let RedirectKind::AppendAll = redirect.kind { libc::O_APPEND } else { libc::O_TRUNC };
+                let fd = self.open(&target, write_flags | flags)?;
+                self.actions.push(FdAction::Dup { from: fd, to: libc::STDOUT_FILENO });
+                self.actions.push(FdAction::Dup { from: libc::STDOUT_FILENO, to: libc::STDERR_FILENO });
+            },
+            RedirectKind::Duplicate => {
+                if target == "-" {
+                    self.actions.push(FdAction::Close(redirect.fd));
+                } else if let Ok(from) = target.parse() {
+                    self.actions.push(FdAction::Dup { from, to: redirect.fd });
+                } else {
+                    return Err(Error::AmbiguousRedirect(target));
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Applies the actions to the current process, returning what's needed to undo them
+    fn apply(&self) -> Vec<(libc::c_int, libc::c_int)> {
+        let mut saved = Vec::with_capacity(self.actions.len());
+        for action in self.actions.iter() {
+            let fd = match *action {
+                FdAction::Dup { to, .. } | FdAction::Close(to) => to,
+            };
+            saved.push((fd, unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) }));
+            unsafe {
+                match *action {
+                    FdAction::Dup { from, to } => libc::dup2(from, to),
+                    FdAction::Close(fd) => libc::close(fd),
+                };
+            }
+        }
+        saved
+    }
+
+    /// For a forked child that runs a builtin instead of exec-ing, so close-on-exec never kicks in.
+    /// Closes the shell's copies of files and pipes, and what `apply` saved, keeping only the fds the actions set up.
+    /// Otherwise a builtin writing to a pipe would hold its read end open too, and never see the reader go away
+    fn close_in_child(&self, saved: Vec<(libc::c_int, libc::c_int)>) {
+        let in_use = |fd| self.actions.iter().any(|action| matches!(*action, FdAction::Dup { to, .. } if to == fd));
+        let saved_fds = saved.into_iter().map(|(_, saved_fd)| saved_fd).filter(|&saved_fd| saved_fd != -1);
+        for fd in self.opened.iter().chain(self.held.iter()).copied().chain(saved_fds) {
+            if !in_use(fd) {
+                unsafe { libc::close(fd) };
+            }
+        }
+    }
+
+    fn restore(saved: Vec<(libc::c_int, libc::c_int)>) {
+        for (fd, saved_fd) in saved.into_iter().rev() {
+            unsafe {
+                if saved_fd == -1 {
+                    libc::close(fd);
+                } else {
+                    libc::dup2(saved_fd, fd);
+                    libc::close(saved_fd);
+                }
+            }
+        }
+    }
+}
+
+/// A command with its words and assignments expanded
+struct Expanded {
+    assignments: Vec<(String, String)>,
+    argv: Vec<String>,
+}
+
+/// Expands a command's words and assignment values. A leading `command`, which only serves to skip aliases, is dropped
+fn expand_command(shell: &mut Shell, command: &Command) -> Result<Expanded, Error> {
+    let mut argv = expand::expand_words(shell, &command.words)?;
+    while argv.len() > 1 && argv[0] == "command" && !argv[1].starts_with('-') {
+        argv.remove(0);
+    }
+
+    let mut assignments = Vec::with_capacity(command.assignments.len());
+    for (name, value) in command.assignments.iter() {
+        assignments.push((name.clone(), expand::expand_string(shell, value)?));
+    }
+
+    Ok(Expanded { assignments, argv })
+}
+
+/// Expands the targets of a command's redirections and adds them
+fn add_redirects(shell: &mut Shell, redirections: &mut Redirections, command: &Command) -> Result<(), Error> {
+    for redirect in command.redirects.iter() {
+        let mut fields = expand::expand_word(shell, &redirect.target)?;
+        if fields.len() != 1 {
+            return Err(Error::RedirectExpansion(redirect.target.to_string()));
+        }
+        redirections.add(redirect, fields.pop().unwrap())?;
+    }
+    Ok(())
+}
+
+fn run_pipeline(shell: &mut Shell, pipeline: &Pipeline) {
+    shell.exit_signal = None;
+
+    // A builtin on its own runs inside the shell so that it can change the shell's state
+    let mut first = None;
+    if pipeline.commands.len() == 1 {
+        let command = &pipeline.commands[0];
+        let expanded = match expand_command(shell, command) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                eprintln!("trash: {}", e);
+                shell.exit_status = e.exit_status();
+                return;
+            }
+        };
+
+        if expanded.argv.is_empty() || builtins::is_builtin(&expanded.argv[0]) {
+            let mut redirections = Redirections::default();
+            if let Err(e) = add_redirects(shell, &mut redirections, command) {
+                eprintln!("trash: {}", e);
+                shell.exit_status = e.exit_status();
+                return;
+            }
+
+            // Without a command, assignments stick. Otherwise they only last as long as the builtin runs
+            // The status is that of the last command substitution, if there was one
+            if expanded.argv.is_empty() {
+                for (name, value) in expanded.assignments {
+                    shell.set_variable(&name, value);
+                }
+                if !command.assignments.iter().any(|(_, value)| value.has_command_substitution()) && !command.redirects.iter().any(|redirect| redirect.target.has_command_substitution()) {
+                    shell.exit_status = 0;
+                }
+                return;
+            }
+
+            let mut saved_variables = Vec::with_capacity(expanded.assignments.len());
+            for (name, value) in expanded.assignments {
+                saved_variables.push((name.clone(), shell.variables.get(&name).cloned()));
+                shell.set_variable(&name, value);
+            }
+
+            let saved = redirections.apply();
+            shell.exit_status = builtins::run(shell, &expanded.argv);
+            let _ = io::stdout().flush();
+            Redirections::restore(saved);
+
+            for (name, variable) in saved_variables.into_iter().rev() {
+                shell.restore_variable(&name, variable);
+            }
+            return;
+        }
+
+        first = Some(expanded);
+    }
+
+    let job = start_pipeline(shell, pipeline, first);
+    shell.exit_status = jobs::foreground(shell, job, false);
+}
+
+/// Forks a copy of the shell into the given process group, or a new one if `pgid` is zero
+fn fork_child(shell: &mut Shell, pgid: libc::pid_t) -> libc::pid_t {
+    let pid = unsafe { libc::fork() };
+
+    if pid == 0 {
+        unsafe {
+            if shell.job_control {
+                libc::setpgid(0, pgid);
+                for &signal in jobs::JOB_CONTROL_SIGNALS {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+            }
+            libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        }
+        // Anything the copy starts belongs to the job it's a part of
+        shell.job_control = false;
+    } else if pid > 0 && shell.job_control {
+        // Also done in the parent so there's no window where the child isn't in the group yet
+        unsafe { libc::setpgid(pid, if pgid == 0 { pid } else { pgid }) };
+    }
+
+    pid
+}
+
+/// Spawns every stage of the pipeline, connected by pipes, without waiting on any of them.
+/// `first` is the first command already expanded, if it has been
+fn start_pipeline(shell: &mut Shell, pipeline: &Pipeline, mut first: Option<Expanded>) -> Job {
+    let mut job = Job::new(pipeline.to_string());
+    let mut stdin_fd: Option<libc::c_int> = None;
+
+    for (i, command) in pipeline.commands.iter().enumerate() {
+
+        let stdout_pipe = if i + 1 < pipeline.commands.len() {
+            let mut fds: [libc::c_int; 2] = [0; 2];
+            if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+                let e = Error::Pipe(error::errno());
+                eprintln!("trash: {}", e);
+                job.processes.push(Process { pid: 0, state: ProcessState::Exited(e.exit_status()) });
+                break;
+            }
+            Some((fds[0], fds[1]))
+        } else {
+            None
+        };
+
+        // The pipe goes first so that something like `2>&1 |` sends stderr down the pipe
+        let mut redirections = Redirections::default();
+        if let Some(fd) = stdin_fd {
+            redirections.opened.push(fd);
+            redirections.actions.push(FdAction::Dup { from: fd, to: libc::STDIN_FILENO });
+        }
+        if let Some((_, write_fd)) = stdout_pipe {
+            redirections.opened.push(write_fd);
+            redirections.actions.push(FdAction::Dup { from: write_fd, to: libc::STDOUT_FILENO });
+        }
+        stdin_fd = stdout_pipe.map(|(read_fd, _)| read_fd);
+        redirections.held.extend(stdin_fd);
+
+        let result: Result<Option<libc::pid_t>, Error> = try {
+            let expanded = match first.take() {
+                Some(expanded) => expanded,
+                None => expand_command(shell, command)?,
+            };
+            add_redirects(shell, &mut redirections, command)?;
+            if expanded.argv.is_empty() {
+                None
+            } else {
+                Some(spawn(shell, &expanded, &redirections, job.pgid)?)
+            }
+        };
+
+        // The child has its own copies of any files now
+        drop(redirections);
+
+        let process = match result {
+            Ok(Some(pid)) => {
+                if shell.job_control && job.pgid == 0 {
+                    job.pgid = pid;
+                }
+                Process { pid, state: ProcessState::Running }
+            },
+            Ok(None) => Process { pid: 0, state: ProcessState::Exited(0) },
+            Err(e) => {
+                eprintln!("trash: {}", e);
+                Process { pid: 0, state: ProcessState::Exited(e.exit_status()) }
+            },
+        };
+        job.processes.push(process);
+    }
+
+    if let Some(fd) = stdin_fd {
+        unsafe { libc::close(fd) };
+    }
+
+    job
+}
+
+/// Starts the command with the given redirections in process group `pgid`, returning the child's pid.
+/// Assignments in front of it only go into its environment
+fn spawn(shell: &mut Shell, expanded: &Expanded, redirections: &Redirections, pgid: libc::pid_t) -> Result<libc::pid_t, Error> {
+    let argv = &expanded.argv;
+
+    // Builtins in a pipeline get their own process, just like any other stage
+    if builtins::is_builtin(&argv[0]) {
+        let pid = fork_child(shell, pgid);
+        if pid == -1 {
+            return Err(Error::Fork(error::errno()));
+        } else if pid == 0 {
+            for (name, value) in expanded.assignments.iter() {
+                shell.set_variable(name, value.clone());
+            }
+            let saved = redirections.apply();
+            redirections.close_in_child(saved);
+            let status = builtins::run(shell, argv);
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(status) };
+        }
+        return Ok(pid);
+    }
+
+    // Path lookup, before anything else so that a missing command fails fast
+    let binary_name = &argv[0];
+    let full_path = match shell.command_hash.resolve(binary_name, &shell.path_list) {
+        Resolution::Found(full_path) => full_path,
+        Resolution::NotExecutable(full_path) => return Err(Error::NotExecutable(full_path.display().to_string())),
+        Resolution::NotFound => return Err(Error::CommandNotFound(binary_name.clone())),
+    };
+    let full_path_string = full_path.display().to_string();
+    let full_path = CString::new(full_path.as_os_str().as_bytes()).map_err(|_| Error::NulInArgument)?;
+
+    let owned_argv: Vec<CString> = argv.iter().map(|arg| CString::new(arg.as_str())).collect::<Result<_, _>>().map_err(|_| Error::NulInArgument)?;
+    let mut argv_ptrs: Vec<*const libc::c_char> = owned_argv.iter().map(|arg| arg.as_ptr()).collect();
+    argv_ptrs.push(std::ptr::null());
+    let environment = shell.environment(&expanded.assignments);
+    let mut envp: Vec<*const libc::c_char> = environment.iter().map(|variable| variable.as_ptr()).collect();
+    envp.push(std::ptr::null());
+
+    unsafe {
+        libc::posix_spawn_file_actions_destroy(&mut shell.spawn_file_actions);
+        libc::posix_spawn_file_actions_init(&mut shell.spawn_file_actions);
+        for action in redirections.actions.iter() {
+            let (ret_val, fd) = match *action {
+                FdAction::Dup { from, to } => {
+                    // Blame whichever fd is out of range
+                    let fd = if from < 0 || from as libc::c_long >= libc::sysconf(libc::_SC_OPEN_MAX) { from } else { to };
+                    (libc::posix_spawn_file_actions_adddup2(&mut shell.spawn_file_actions, from, to), fd)
+                },
+                FdAction::Close(fd) => (libc::posix_spawn_file_actions_addclose(&mut shell.spawn_file_actions, fd), fd),
+            };
+            if ret_val != 0 {
+                return Err(Error::Redirect(fd.to_string(), ret_val));
+            }
+        }
+
+        let mut flags = libc::POSIX_SPAWN_SETSIGDEF;
+        if shell.job_control {
+            flags |= libc::POSIX_SPAWN_SETPGROUP;
+            libc::posix_spawnattr_setpgroup(&mut shell.spawn_attributes, pgid);
+        }
+        libc::posix_spawnattr_setflags(&mut shell.spawn_attributes, flags as libc::c_short);
+    }
+
+    let mut pid: libc::pid_t = 0;
+    let ret_val = unsafe { libc::posix_spawn(&mut pid as *mut libc::pid_t, full_path.as_ptr(), &shell.spawn_file_actions, &shell.spawn_attributes, argv_ptrs.as_ptr() as *const *mut libc::c_char, envp.as_ptr() as *const *mut libc::c_char) };
+
+    match ret_val {
+        0 => Ok(pid),
+        libc::EACCES => Err(Error::NotExecutable(full_path_string)),
+        errno => Err(Error::Spawn(binary_name.clone(), errno)),
+    }
+}
@@ -0,0 +1,279 @@
+use std::ffi::{CStr, CString};
+
+use crate::arithmetic;
+use crate::brace;
+use crate::error::Error;
+use crate::exec;
+use crate::glob;
+use crate::parser::{self, ModifierKind, Parameter, Word, WordPart};
+use crate::shell::Shell;
+
+/// Some of the text a word expands to, and whether it's subject to field splitting
+struct Piece {
+    text: String,
+    split: bool,
+    /// Starts a new field even though it isn't split, as between the parameters of `"$@"`
+    new_field: bool,
+}
+
+impl Piece {
+    fn new(text: String, split: bool) -> Piece {
+        Piece { text, split, new_field: false }
+    }
+}
+
+fn expand_parts(shell: &mut Shell, parts: &[WordPart], quoted: bool, pieces: &mut Vec<Piece>) -> Result<(), Error> {
+    for part in parts {
+        match *part {
+            // Bare text never holds blanks unless it came from inside a `${...}`, where they do get split
+            WordPart::Bare(ref text) => pieces.push(Piece::new(text.clone(), !quoted)),
+            WordPart::Quoted(ref text) => pieces.push(Piece::new(text.clone(), false)),
+            WordPart::DoubleQuoted(ref inner) => {
+                // `"$@"` with no parameters is the one thing in double quotes that makes no word at all
+                if let [WordPart::Parameter(Parameter { ref name, modifier: None })] = inner.as_slice() {
+                    if name == "@" && shell.positional.is_empty() {
+                        continue;
+                    }
+                }
+
+                // Even `""` makes a word
+                pieces.push(Piece::new(String::new(), false));
+                expand_parts(shell, inner, true, pieces)?;
+            },
+            WordPart::Parameter(ref parameter) => expand_parameter(shell, parameter, quoted, pieces)?,
+            WordPart::CommandSubstitution(ref source) => {
+                let mut output = exec::substitute(shell, source)?;
+                output.truncate(output.trim_end_matches('\n').len());
+                pieces.push(Piece::new(output, !quoted));
+            },
+            WordPart::Arithmetic(ref expression) => {
+                let expression = expand_string(shell, expression)?;
+                let value = arithmetic::evaluate(shell, &expression)?;
+                pieces.push(Piece::new(value.to_string(), !quoted));
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// The value of a special, positional, or regular parameter, None if it's unset
+fn parameter_value(shell: &Shell, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(shell.exit_status.to_string()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.last_background_pid.map(|pid| pid.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        "0" => Some(shell.name.clone()),
+        "@" | "*" if shell.positional.is_empty() => None,
+        "@" | "*" => Some(shell.positional.join(" ")),
+        // `${00}` and the like are still `$0`
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => match name.parse::<usize>().ok()?.checked_sub(1) {
+            Some(index) => shell.positional.get(index).cloned(),
+            None => Some(shell.name.clone()),
+        },
+        _ => shell.variable(name).map(|value| value.to_string()),
+    }
+}
+
+fn expand_parameter(shell: &mut Shell, parameter: &Parameter, quoted: bool, pieces: &mut Vec<Piece>) -> Result<(), Error> {
+    let value = parameter_value(shell, &parameter.name);
+    let modifier = match parameter.modifier {
+        Some(ref modifier) => modifier,
+        // Each parameter is its own word in `"$@"`
+        None if quoted && parameter.name == "@" => {
+            for (i, value) in shell.positional.iter().enumerate() {
+                pieces.push(Piece { text: value.clone(), split: false, new_field: i > 0 });
+            }
+            return Ok(());
+        },
+        None => {
+            pieces.push(Piece::new(value.unwrap_or_default(), !quoted));
+            return Ok(());
+        }
+    };
+
+    let unset = match value {
+        Some(ref value) => modifier.check_empty && value.is_empty(),
+        None => true,
+    };
+
+    match (modifier.kind, unset) {
+        (ModifierKind::Default, true) | (ModifierKind::Alternative, false) => expand_parts_with_tilde(shell, &modifier.word.parts, quoted, pieces)?,
+        (ModifierKind::Alternative, true) => (),
+        (ModifierKind::Assign, true) if !parser::is_name(&parameter.name) => {
+            return Err(Error::ParameterUnset(parameter.name.clone(), "cannot assign in this way".to_string()));
+        },
+        (ModifierKind::Assign, true) => {
+            let value = expand_string(shell, &modifier.word)?;
+            shell.set_variable(&parameter.name, value.clone());
+            pieces.push(Piece::new(value, !quoted));
+        },
+        (ModifierKind::Error, true) => {
+            let message = if modifier.word.parts.is_empty() {
+                "parameter null or not set".to_string()
+            } else {
+                expand_string(shell, &modifier.word)?
+            };
+            return Err(Error::ParameterUnset(parameter.name.clone(), message));
+        },
+        (_, false) => pieces.push(Piece::new(value.unwrap_or_default(), !quoted)),
+    }
+
+    Ok(())
+}
+
+/// A word after field splitting
+struct Field {
+    text: String,
+    /// The text with quoted characters escaped, for matching against file names
+    pattern: String,
+}
+
+/// Characters that mean something in a pattern, and so are escaped when they were quoted
+const PATTERN_CHARACTERS: &str = "*?[]\\";
+
+/// Joins pieces into fields, splitting the ones that came from unquoted expansions on blanks
+fn split_fields(pieces: Vec<Piece>) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut field: Option<Field> = None;
+    let new_field = || Field { text: String::new(), pattern: String::new() };
+
+    for piece in pieces {
+        if piece.new_field {
+            fields.extend(field.take());
+        }
+
+        if !piece.split {
+            let field = field.get_or_insert_with(new_field);
+            field.text.push_str(&piece.text);
+            for c in piece.text.chars() {
+                if PATTERN_CHARACTERS.contains(c) {
+                    field.pattern.push('\\');
+                }
+                field.pattern.push(c);
+            }
+            continue;
+        }
+
+        for c in piece.text.chars() {
+            if let ' ' | '\t' | '\n' = c {
+                fields.extend(field.take());
+            } else {
+                let field = field.get_or_insert_with(new_field);
+                field.text.push(c);
+                field.pattern.push(c);
+            }
+        }
+    }
+
+    fields.extend(field);
+    fields
+}
+
+/// The home directory of a user, from the password database
+pub fn user_home_dir(name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        let pwid_ptr = loop {
+            *libc::__errno_location() = 0;
+            let pwid_ptr = libc::getpwnam(name.as_ptr());
+            if !pwid_ptr.is_null() || *libc::__errno_location() != libc::EINTR {
+                break pwid_ptr;
+            }
+        };
+
+        if pwid_ptr.is_null() {
+            return None;
+        }
+        CStr::from_ptr((*pwid_ptr).pw_dir).to_str().ok().map(|dir| dir.to_string())
+    }
+}
+
+/// Expands `~` or `~user` at the start of a path. Left alone if there's no such user
+pub fn expand_home(path: &str, home_dir: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return path.to_string(),
+    };
+
+    let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if user.is_empty() {
+        return format!("{}{}", home_dir, rest);
+    }
+    match user_home_dir(user) {
+        Some(dir) => format!("{}{}", dir, rest),
+        None => path.to_string(),
+    }
+}
+
+/// What `~`, `~user`, `~+`, or `~-` stands for, given what follows the `~`
+fn tilde_prefix(shell: &Shell, prefix: &str) -> Option<String> {
+    match prefix {
+        "" => Some(shell.variable("HOME").unwrap_or(&shell.home_dir).to_string()),
+        "+" => shell.variable("PWD").map(|dir| dir.to_string()),
+        "-" => shell.variable("OLDPWD").map(|dir| dir.to_string()),
+        user => user_home_dir(user),
+    }
+}
+
+/// Like `expand_parts`, but first expands a `~` at the start of the word.
+/// The tilde prefix runs up to the first `/`, and isn't expanded if any of it is quoted
+fn expand_parts_with_tilde(shell: &mut Shell, parts: &[WordPart], quoted: bool, pieces: &mut Vec<Piece>) -> Result<(), Error> {
+    if let Some(WordPart::Bare(ref text)) = parts.first() {
+        if let Some(rest) = text.strip_prefix('~') {
+            let end = rest.find('/').unwrap_or(rest.len());
+            let whole_prefix = end < rest.len() || parts.len() == 1;
+            if let Some(dir) = tilde_prefix(shell, &rest[..end]).filter(|_| whole_prefix) {
+                // What the tilde stands for is never split
+                pieces.push(Piece::new(dir, false));
+                pieces.push(Piece::new(rest[end..].to_string(), !quoted));
+                return expand_parts(shell, &parts[1..], quoted, pieces);
+            }
+        }
+    }
+
+    expand_parts(shell, parts, quoted, pieces)
+}
+
+/// Expands a word into the fields it stands for, which can be none at all, matching any globs against file names
+pub fn expand_word(shell: &mut Shell, word: &Word) -> Result<Vec<String>, Error> {
+    let mut pieces = Vec::new();
+    expand_parts_with_tilde(shell, &word.parts, false, &mut pieces)?;
+
+    let mut fields = Vec::new();
+    for field in split_fields(pieces) {
+        if !glob::has_magic(&field.pattern) {
+            fields.push(field.text);
+            continue;
+        }
+
+        let paths = glob::expand(&field.pattern, shell.options.dotglob);
+        if !paths.is_empty() {
+            fields.extend(paths);
+        } else if shell.options.failglob {
+            return Err(Error::NoMatch(field.text));
+        } else if !shell.options.nullglob {
+            fields.push(field.text);
+        }
+    }
+    Ok(fields)
+}
+
+/// Expands the words of a command, starting with brace expansion
+pub fn expand_words(shell: &mut Shell, words: &[Word]) -> Result<Vec<String>, Error> {
+    let mut fields = Vec::with_capacity(words.len());
+    for word in words {
+        for word in brace::expand(word) {
+            fields.extend(expand_word(shell, &word)?);
+        }
+    }
+    Ok(fields)
+}
+
+/// Expands a word into a single string without splitting it, as for the value of an assignment
+pub fn expand_string(shell: &mut Shell, word: &Word) -> Result<String, Error> {
+    let mut pieces = Vec::new();
+    expand_parts_with_tilde(shell, &word.parts, true, &mut pieces)?;
+    Ok(pieces.into_iter().map(|piece| piece.text).collect())
+}
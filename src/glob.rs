@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::Path;
+
+/// Whether `text` matches the shell pattern, which can use `*`, `?`, and `[...]` classes
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_from(&pattern, &text)
+}
+
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to go back to if what followed the last `*` stops matching
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            },
+            Some('?') => Some(1),
+            Some('[') => match_class(&pattern[p..], text[t]),
+            Some('\\') if p + 1 < pattern.len() => if pattern[p + 1] == text[t] { Some(2) } else { None },
+            Some(&c) => if c == text[t] { Some(1) } else { None },
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            },
+            // Let the last `*` eat one more character and try again
+            (None, Some((star, star_t))) => {
+                backtrack = Some((star, star_t + 1));
+                p = star + 1;
+                t = star_t + 1;
+            },
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches a `[...]` class at the start of `pattern`, returning how much of the pattern it took up if `c` is in it.
+/// An unterminated `[` is just a `[`
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let start = match pattern.get(i) {
+            Some(']') if !first => break,
+            Some(&start) => start,
+            None => return if c == '[' { Some(1) } else { None },
+        };
+        first = false;
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).map(|&end| end != ']').unwrap_or(false) {
+            if start <= c && c <= pattern[i + 2] {
+                found = true;
+            }
+            i += 3;
+        } else {
+            if start == c {
+                found = true;
+            }
+            i += 1;
+        }
+    }
+
+    if found != negated {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
+
+/// Whether a pattern has any unescaped `*`, `?`, or `[`, and so needs matching against file names
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            },
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// Drops the escapes from a pattern without any magic in it
+fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// The paths matching a pattern, sorted. A `**` component matches any number of directories.
+/// Hidden files only match a pattern starting with `.`, unless `dotglob` is on
+pub fn expand(pattern: &str, dotglob: bool) -> Vec<String> {
+    let (base, pattern) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", pattern),
+    };
+
+    let components: Vec<&str> = pattern.split('/').collect();
+    let mut paths = Vec::new();
+    expand_from(base, &components, dotglob, &mut paths);
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// The names in a directory, where `base` is empty for the current directory or ends with a `/`
+fn read_names(base: &str, pattern: &str, dotglob: bool) -> Vec<String> {
+    let entries = match fs::read_dir(if base.is_empty() { "." } else { base }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries.flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| dotglob || !name.starts_with('.') || pattern.starts_with('.'))
+        .collect()
+}
+
+fn expand_from(base: &str, components: &[&str], dotglob: bool, paths: &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some((&component, rest)) => (component, rest),
+        None => return,
+    };
+
+    if component.is_empty() {
+        // A trailing `/` only matches directories
+        if rest.is_empty() {
+            if !base.is_empty() && Path::new(base).is_dir() {
+                paths.push(base.to_string());
+            }
+        } else {
+            expand_from(base, rest, dotglob, paths);
+        }
+    } else if component == "**" {
+        // Zero directories, then one more level down with `**` still to go
+        if rest.is_empty() {
+            for name in read_names(base, "", dotglob) {
+                paths.push(format!("{}{}", base, name));
+            }
+        } else {
+            expand_from(base, rest, dotglob, paths);
+        }
+        for name in read_names(base, "", dotglob) {
+            let path = format!("{}{}", base, name);
+            // Symlinks to directories aren't followed, so a link back up can't loop forever
+            let is_dir = fs::symlink_metadata(&path).map(|metadata| metadata.is_dir()).unwrap_or(false);
+            if is_dir {
+                expand_from(&format!("{}/", path), components, dotglob, paths);
+            }
+        }
+    } else if !has_magic(component) {
+        let path = format!("{}{}", base, unescape(component));
+        if rest.is_empty() {
+            if fs::symlink_metadata(&path).is_ok() {
+                paths.push(path);
+            }
+        } else {
+            expand_from(&format!("{}/", path), rest, dotglob, paths);
+        }
+    } else {
+        for name in read_names(base, component, dotglob) {
+            if !matches(component, &name) {
+                continue;
+            }
+            let path = format!("{}{}", base, name);
+            if rest.is_empty() {
+                paths.push(path);
+            } else if Path::new(&path).is_dir() {
+                expand_from(&format!("{}/", path), rest, dotglob, paths);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(pattern: &str, matching: &[&str], not_matching: &[&str]) {
+        for text in matching {
+            assert!(matches(pattern, text), "{:?} should match {:?}", pattern, text);
+        }
+        for text in not_matching {
+            assert!(!matches(pattern, text), "{:?} shouldn't match {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn literals() {
+        check("abc", &["abc"], &["ab", "abcd", "xabc", ""]);
+        check("", &[""], &["a"]);
+        check("é✓", &["é✓"], &["e✓"]);
+    }
+
+    #[test]
+    fn wildcards() {
+        check("*", &["", "a", "abc", ".hidden"], &[]);
+        check("a*", &["a", "abc"], &["ba", ""]);
+        check("*.rs", &["main.rs", ".rs", "a.b.rs"], &["main.rsx", "rs"]);
+        check("a*b*c", &["abc", "aXbYc", "abbbc", "acbc"], &["ab", "acb", "abcd"]);
+        check("*a*a*", &["aa", "banana"], &["a", "bob"]);
+        check("**", &["", "x"], &[]);
+        check("?", &["a", "✓"], &["", "ab"]);
+        check("a?c", &["abc", "a?c"], &["ac", "abbc"]);
+        check("?*?", &["ab", "abc"], &["a"]);
+    }
+
+    #[test]
+    fn classes() {
+        check("[abc]", &["a", "b", "c"], &["d", "", "ab"]);
+        check("[a-c]x", &["ax", "bx", "cx"], &["dx", "-x"]);
+        check("[!a-c]", &["d", "-"], &["a", "b", ""]);
+        check("[^a]", &["b"], &["a"]);
+        check("[]a]", &["]", "a"], &["b"]);
+        check("[!]]", &["a"], &["]"]);
+        check("[a-]", &["a", "-"], &["b"]);
+        check("[*?]", &["*", "?"], &["a"]);
+        check("[0-9][0-9]", &["42"], &["4", "4a"]);
+        check("*[0-9]", &["file1", "9"], &["file"]);
+    }
+
+    #[test]
+    fn unterminated_class() {
+        check("[", &["["], &["", "a"]);
+        check("[ab", &["[ab"], &["a", "[a"]);
+        check("a[", &["a["], &["a"]);
+    }
+
+    #[test]
+    fn escapes() {
+        check("\\*", &["*"], &["a", "\\*"]);
+        check("a\\?c", &["a?c"], &["abc"]);
+        check("\\[a]", &["[a]"], &["a"]);
+        check("\\\\", &["\\"], &["\\\\"]);
+        check("a\\", &["a\\"], &["a"]);
+        check("*\\*", &["*", "ab*"], &["ab"]);
+    }
+
+    #[test]
+    fn magic() {
+        for pattern in ["*", "a?", "[ab]", "x\\\\*"] {
+            assert!(has_magic(pattern), "{:?} should have magic", pattern);
+        }
+        for pattern in ["", "abc", "\\*", "a\\?\\[b]", "]"] {
+            assert!(!has_magic(pattern), "{:?} shouldn't have magic", pattern);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::lexer::{self, Token};
+use crate::shell::Options;
+
+pub struct Entry {
+    /// Seconds since the epoch, 0 if we don't know
+    pub timestamp: u64,
+    pub line: String,
+}
+
+/// Holds an flock on a file until dropped, so several shells can share a history file
+struct Lock<'a> {
+    file: &'a File,
+}
+
+impl<'a> Lock<'a> {
+    fn new(file: &'a File, operation: libc::c_int) -> io::Result<Lock<'a>> {
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(Lock { file });
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+impl Drop for Lock<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Every line entered, kept in ~/.trash_history as a `#timestamp` line followed by the command
+#[derive(Default)]
+pub struct History {
+    pub entries: Vec<Entry>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// Loads the history file in the user's home directory, a missing file is just an empty history
+    pub fn load(home_dir: &str) -> io::Result<History> {
+        let mut path = PathBuf::from(home_dir);
+        path.push(".trash_history");
+
+        let mut history = History { entries: Vec::new(), path: Some(path.clone()) };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e),
+        };
+
+        let _lock = Lock::new(&file, libc::LOCK_SH)?;
+        let mut timestamp = 0;
+        for line in BufReader::new(&file).lines() {
+            let line = line?;
+            if let Some(value) = line.strip_prefix('#') {
+                if let Ok(value) = value.parse() {
+                    timestamp = value;
+                    continue;
+                }
+            }
+
+            history.entries.push(Entry { timestamp, line });
+            timestamp = 0;
+        }
+
+        Ok(history)
+    }
+
+    /// Records a line, unless the history options say to skip it. Returns whether it was recorded
+    pub fn add(&mut self, line: &str, options: &Options) -> io::Result<bool> {
+        if line.trim().is_empty()
+            || (options.histignorespace && line.starts_with(' '))
+            || (options.histignoredups && self.entries.last().map(|entry| entry.line == line).unwrap_or(false)) {
+            return Ok(false);
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+        self.entries.push(Entry { timestamp, line: line.to_string() });
+
+        if let Some(ref path) = self.path {
+            let file = OpenOptions::new().append(true).create(true).open(path)?;
+            let _lock = Lock::new(&file, libc::LOCK_EX)?;
+            // A single write so that even without the lock, appends from other shells can't land in the middle
+            (&file).write_all(format!("#{}\n{}\n", timestamp, line).as_bytes())?;
+        }
+
+        Ok(true)
+    }
+
+    /// Forgets everything, including what's in the history file
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.entries.clear();
+
+        if let Some(ref path) = self.path {
+            let file = match OpenOptions::new().write(true).open(path) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let _lock = Lock::new(&file, libc::LOCK_EX)?;
+            file.set_len(0)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits a line into words the way history references see them, with operators as words of their own
+fn words(line: &str) -> Vec<String> {
+    match lexer::tokenize(line) {
+        Ok(tokens) => tokens.iter().filter_map(|token| match *token {
+            Token::Word(ref word) => Some(word.to_string()),
+            Token::Newline => None,
+            ref token => Some(token.describe().to_string()),
+        }).collect(),
+        Err(_) => line.split_whitespace().map(|word| word.to_string()).collect(),
+    }
+}
+
+/// Expands csh style history references: `!!`, `!$`, `!^`, `!*`, `!n`, `!-n`, `!prefix`, and `^old^new` at the start of a line.
+/// Returns None if there was nothing to expand
+pub fn expand(line: &str, entries: &[Entry]) -> Result<Option<String>, Error> {
+    if let Some(rest) = line.strip_prefix('^') {
+        let mut parts = rest.trim_end_matches('\n').splitn(3, '^');
+        let old = parts.next().unwrap_or("");
+        let new = parts.next().unwrap_or("");
+        let previous = match entries.last() {
+            Some(entry) if !old.is_empty() && entry.line.contains(old) => &entry.line,
+            _ => return Err(Error::HistorySubstitution(line.trim_end().to_string())),
+        };
+
+        let mut expanded = previous.replacen(old, new, 1);
+        expanded.push_str(parts.next().unwrap_or(""));
+        expanded.push('\n');
+        return Ok(Some(expanded));
+    }
+
+    let mut expanded = String::with_capacity(line.len());
+    let mut changed = false;
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        i += c.len_utf8();
+        match c {
+            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+            '\\' if !in_single_quotes => {
+                expanded.push(c);
+                if let Some(next) = line[i..].chars().next() {
+                    expanded.push(next);
+                    i += next.len_utf8();
+                }
+                continue;
+            },
+            '!' if !in_single_quotes => {
+                let rest = &line[i..];
+                let event_len = match rest.chars().next() {
+                    Some('!') | Some('$') | Some('^') | Some('*') => 1,
+                    Some('-') => 1 + rest[1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - 1),
+                    Some(c) if c.is_ascii_digit() => rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()),
+                    // A lone `!` is just a `!`, as in `! command` or `!=`
+                    Some(c) if !c.is_whitespace() && !"=(;&|<>\"".contains(c) => rest.find(|c: char| c.is_whitespace() || ";&|<>\"".contains(c)).unwrap_or(rest.len()),
+                    _ => 0,
+                };
+                if event_len == 0 || &rest[..event_len] == "-" {
+                    expanded.push(c);
+                    continue;
+                }
+
+                let event = &rest[..event_len];
+                let not_found = || Error::HistoryEvent(format!("!{}", event));
+                let entry = match event {
+                    "!" | "$" | "^" | "*" => entries.last(),
+                    _ if event.starts_with('-') => event[1..].parse::<usize>().ok().and_then(|n| entries.len().checked_sub(n)).and_then(|index| entries.get(index)),
+                    _ if event.starts_with(|c: char| c.is_ascii_digit()) => event.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|index| entries.get(index)),
+                    _ => entries.iter().rev().find(|entry| entry.line.starts_with(event)),
+                }.ok_or_else(not_found)?;
+
+                let replacement = match event {
+                    "$" => words(&entry.line).pop().ok_or_else(not_found)?,
+                    "^" => words(&entry.line).get(1).cloned().ok_or_else(not_found)?,
+                    "*" => words(&entry.line).get(1..).map(|args| args.join(" ")).unwrap_or_default(),
+                    _ => entry.line.clone(),
+                };
+
+                expanded.push_str(&replacement);
+                changed = true;
+                i += event_len;
+                continue;
+            },
+            _ => (),
+        }
+        expanded.push(c);
+    }
+
+    Ok(if changed { Some(expanded) } else { None })
+}
+
+extern "C" {
+    // Not exposed by the libc crate
+    fn strftime(s: *mut libc::c_char, max: libc::size_t, format: *const libc::c_char, tm: *const libc::tm) -> libc::size_t;
+}
+
+/// Formats a timestamp in local time, like `2020-01-31 13:45:00`
+pub fn format_timestamp(timestamp: u64) -> String {
+    if timestamp == 0 {
+        return " ".repeat(19);
+    }
+
+    let mut buffer = [0u8; 32];
+    let len = unsafe {
+        let time = timestamp as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return " ".repeat(19);
+        }
+        strftime(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), b"%Y-%m-%d %H:%M:%S\0".as_ptr() as *const libc::c_char, &tm)
+    };
+
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
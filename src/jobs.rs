@@ -0,0 +1,417 @@
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{self, Error};
+use crate::shell::Shell;
+
+/// Signals that should reach the foreground job rather than the shell
+pub const JOB_CONTROL_SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+static CHILD_STATUS_CHANGED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigchld(_: libc::c_int) {
+    CHILD_STATUS_CHANGED.store(true, Ordering::SeqCst);
+}
+
+/// Lets us know when background jobs need reaping, without interrupting reads of input
+pub fn install_sigchld_handler() {
+    unsafe {
+        let mut action: libc::sigaction = MaybeUninit::zeroed().assume_init();
+        action.sa_sigaction = on_sigchld as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut());
+    }
+}
+
+/// Puts the shell in its own process group in control of the terminal, if there is one
+pub fn init_job_control(shell: &mut Shell) {
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return;
+        }
+
+        // If we were started in the background, wait until we're brought to the foreground
+        loop {
+            let pgid = libc::getpgrp();
+            if libc::tcgetpgrp(libc::STDIN_FILENO) == pgid {
+                break;
+            }
+            libc::kill(-pgid, libc::SIGTTIN);
+        }
+
+        for &signal in JOB_CONTROL_SIGNALS {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+
+        let pid = libc::getpid();
+        if libc::getpgrp() != pid && libc::setpgid(0, pid) == -1 {
+            eprintln!("trash: couldn't put the shell in its own process group, job control is disabled");
+            return;
+        }
+        libc::tcsetpgrp(libc::STDIN_FILENO, pid);
+
+        let mut terminal_modes: MaybeUninit<libc::termios> = MaybeUninit::uninit();
+        if libc::tcgetattr(libc::STDIN_FILENO, terminal_modes.as_mut_ptr()) == 0 {
+            shell.terminal_modes = Some(terminal_modes.assume_init());
+        }
+
+        shell.pgid = pid;
+        shell.job_control = true;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProcessState {
+    Running,
+    /// Holds the signal that stopped it
+    Stopped(i32),
+    Exited(i32),
+    Signaled { signal: i32, core_dumped: bool },
+}
+
+impl ProcessState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, ProcessState::Exited(_) | ProcessState::Signaled { .. })
+    }
+
+    /// The exit status the shell reports, with the usual 128 + N for signals
+    pub fn status(self) -> i32 {
+        match self {
+            ProcessState::Running => 0,
+            ProcessState::Exited(status) => status,
+            ProcessState::Stopped(signal) | ProcessState::Signaled { signal, .. } => 128 + signal,
+        }
+    }
+}
+
+/// The short name of a signal, like SEGV for SIGSEGV
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "HUP",
+        libc::SIGINT => "INT",
+        libc::SIGQUIT => "QUIT",
+        libc::SIGILL => "ILL",
+        libc::SIGTRAP => "TRAP",
+        libc::SIGABRT => "ABRT",
+        libc::SIGBUS => "BUS",
+        libc::SIGFPE => "FPE",
+        libc::SIGKILL => "KILL",
+        libc::SIGUSR1 => "USR1",
+        libc::SIGSEGV => "SEGV",
+        libc::SIGUSR2 => "USR2",
+        libc::SIGPIPE => "PIPE",
+        libc::SIGALRM => "ALRM",
+        libc::SIGTERM => "TERM",
+        libc::SIGSTKFLT => "STKFLT",
+        libc::SIGCHLD => "CHLD",
+        libc::SIGCONT => "CONT",
+        libc::SIGSTOP => "STOP",
+        libc::SIGTSTP => "TSTP",
+        libc::SIGTTIN => "TTIN",
+        libc::SIGTTOU => "TTOU",
+        libc::SIGURG => "URG",
+        libc::SIGXCPU => "XCPU",
+        libc::SIGXFSZ => "XFSZ",
+        libc::SIGVTALRM => "VTALRM",
+        libc::SIGPROF => "PROF",
+        libc::SIGWINCH => "WINCH",
+        libc::SIGIO => "IO",
+        libc::SIGPWR => "PWR",
+        libc::SIGSYS => "SYS",
+        _ => return format!("SIG{}", signal),
+    };
+    name.into()
+}
+
+/// A description of how a signal ended a process, like "Segmentation fault (core dumped)"
+pub fn signal_description(signal: i32, core_dumped: bool) -> String {
+    let mut description = unsafe { CStr::from_ptr(libc::strsignal(signal)).to_string_lossy().into_owned() };
+    if core_dumped {
+        description.push_str(" (core dumped)");
+    }
+    description
+}
+
+pub struct Process {
+    /// Zero if the process was never started, in which case it's already finished
+    pub pid: libc::pid_t,
+    pub state: ProcessState,
+}
+
+/// Decodes a status from waitpid
+fn process_state(wstatus: libc::c_int) -> ProcessState {
+    if libc::WIFSTOPPED(wstatus) {
+        ProcessState::Stopped(libc::WSTOPSIG(wstatus))
+    } else if libc::WIFCONTINUED(wstatus) {
+        ProcessState::Running
+    } else if libc::WIFSIGNALED(wstatus) {
+        ProcessState::Signaled { signal: libc::WTERMSIG(wstatus), core_dumped: libc::WCOREDUMP(wstatus) }
+    } else {
+        ProcessState::Exited(libc::WEXITSTATUS(wstatus))
+    }
+}
+
+/// A pipeline, or a subshell running an and-or list, tracked as one unit
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub processes: Vec<Process>,
+    /// Zero unless job control is on, in which case every process is in this group
+    pub pgid: libc::pid_t,
+    /// The terminal modes the job had when it was stopped, to give back when it resumes
+    pub terminal_modes: Option<libc::termios>,
+}
+
+impl Job {
+    pub fn new(command: String) -> Job {
+        Job {
+            id: 0,
+            command,
+            processes: Vec::with_capacity(4),
+            pgid: 0,
+            terminal_modes: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.processes.iter().any(|process| process.state == ProcessState::Running)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.processes.iter().all(|process| process.state.is_finished())
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        !self.is_running() && !self.is_done()
+    }
+
+    /// Sends SIGCONT to every process in the job
+    pub fn resume(&mut self) {
+        unsafe {
+            if self.pgid != 0 {
+                libc::kill(-self.pgid, libc::SIGCONT);
+            } else {
+                for process in self.processes.iter().filter(|process| process.pid != 0) {
+                    libc::kill(process.pid, libc::SIGCONT);
+                }
+            }
+        }
+
+        for process in self.processes.iter_mut() {
+            if let ProcessState::Stopped(_) = process.state {
+                process.state = ProcessState::Running;
+            }
+        }
+    }
+
+    /// The process whose status is the job's status: the last one, or with pipefail, the last one to fail
+    fn reported_process(&self, pipefail: bool) -> Option<&Process> {
+        if pipefail {
+            self.processes.iter().rev().find(|process| process.state.status() != 0)
+        } else {
+            self.processes.last()
+        }
+    }
+
+    pub fn status(&self, pipefail: bool) -> i32 {
+        self.reported_process(pipefail).map(|process| process.state.status()).unwrap_or(0)
+    }
+
+    /// The signal that killed the job, if that's where its status came from
+    pub fn signal(&self, pipefail: bool) -> Option<i32> {
+        match self.reported_process(pipefail)?.state {
+            ProcessState::Signaled { signal, .. } => Some(signal),
+            _ => None,
+        }
+    }
+
+    pub fn state_description(&self, pipefail: bool) -> String {
+        if self.is_running() {
+            "Running".into()
+        } else if self.is_stopped() {
+            "Stopped".into()
+        } else {
+            match self.reported_process(pipefail).map(|process| process.state) {
+                Some(ProcessState::Signaled { signal, core_dumped }) => signal_description(signal, core_dumped),
+                Some(ProcessState::Exited(status)) if status != 0 => format!("Exit {}", status),
+                _ => "Done".into(),
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+}
+
+impl JobTable {
+    /// Takes ownership of the job, giving it the lowest free job number if it doesn't have one already
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 || self.jobs.iter().any(|other| other.id == job.id) {
+            let mut id = 1;
+            while self.jobs.iter().any(|job| job.id == id) {
+                id += 1;
+            }
+            job.id = id;
+        }
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Job> {
+        self.jobs.iter()
+    }
+
+    /// The job `fg` and `bg` act on by default, the most recently started one
+    pub fn current(&self) -> Option<usize> {
+        self.jobs.last().map(|job| job.id)
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        self.jobs.iter().rev().nth(1).map(|job| job.id)
+    }
+
+    /// Collects the status of any children that have changed since the last SIGCHLD, without blocking
+    pub fn reap(&mut self) {
+        if !CHILD_STATUS_CHANGED.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        for process in self.jobs.iter_mut().flat_map(|job| job.processes.iter_mut()) {
+            if process.state.is_finished() {
+                continue;
+            }
+
+            let mut wstatus: libc::c_int = 0;
+            let pid = unsafe { libc::waitpid(process.pid, &mut wstatus, libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED) };
+            if pid == process.pid {
+                process.state = process_state(wstatus);
+            }
+        }
+    }
+
+    /// Removes and returns every job that has finished
+    pub fn take_finished(&mut self) -> Vec<Job> {
+        let mut finished = Vec::new();
+        let mut i = 0;
+        while i < self.jobs.len() {
+            if self.jobs[i].is_done() {
+                finished.push(self.jobs.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        finished
+    }
+
+    /// Parses a job specifier like `%2`, `%%`, `%+`, `%-`, or a plain job number
+    pub fn parse_spec(&self, spec: &str) -> Option<usize> {
+        match spec {
+            "%%" | "%+" | "%" => self.current(),
+            "%-" => self.previous(),
+            _ => {
+                let id: usize = spec.trim_start_matches('%').parse().ok()?;
+                self.jobs.iter().find(|job| job.id == id).map(|job| job.id)
+            },
+        }
+    }
+
+    /// The marker `jobs` shows next to a job, `+` for the current job and `-` for the previous
+    pub fn marker(&self, id: usize) -> char {
+        if Some(id) == self.current() {
+            '+'
+        } else if Some(id) == self.previous() {
+            '-'
+        } else {
+            ' '
+        }
+    }
+}
+
+/// Gives the job the terminal, resuming it first if asked to, and waits until it finishes or stops
+pub fn foreground(shell: &mut Shell, mut job: Job, resume: bool) -> i32 {
+    if shell.job_control && job.pgid != 0 {
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, job.pgid);
+            if let (true, Some(modes)) = (resume, job.terminal_modes.as_ref()) {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes);
+            }
+        }
+    }
+
+    if resume {
+        job.resume();
+    }
+
+    wait_for(&mut job);
+
+    if shell.job_control && job.pgid != 0 {
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, shell.pgid);
+            if job.is_stopped() {
+                let mut modes: MaybeUninit<libc::termios> = MaybeUninit::uninit();
+                if libc::tcgetattr(libc::STDIN_FILENO, modes.as_mut_ptr()) == 0 {
+                    job.terminal_modes = Some(modes.assume_init());
+                }
+            }
+            if let Some(modes) = shell.terminal_modes.as_ref() {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes);
+            }
+        }
+    }
+
+    let status = job.status(shell.options.pipefail);
+    shell.exit_signal = job.signal(shell.options.pipefail);
+    if let Some(&ProcessState::Signaled { signal, core_dumped }) = job.processes.iter().rev().map(|process| &process.state).find(|state| matches!(state, ProcessState::Signaled { .. })) {
+        // Like other shells, stay quiet about signals that are usually sent on purpose
+        match signal {
+            libc::SIGINT => eprintln!(),
+            libc::SIGPIPE => (),
+            _ => eprintln!("{}", signal_description(signal, core_dumped)),
+        }
+    }
+    if job.is_stopped() {
+        let command = job.command.clone();
+        let id = shell.jobs.add(job);
+        eprintln!("\n[{}]+ {:<24}{}", id, "Stopped", command);
+    }
+
+    status
+}
+
+/// Blocks until no process in `job` is running
+pub fn wait_for(job: &mut Job) {
+    for process in job.processes.iter_mut() {
+        while process.state == ProcessState::Running {
+            let mut wstatus: i32 = 0;
+            let wait_ret_val = unsafe { libc::waitpid(process.pid, &mut wstatus as *mut i32, libc::WUNTRACED) };
+            if wait_ret_val == -1 {
+                let errno = error::errno();
+                if errno == libc::EINTR {
+                    continue;
+                }
+
+                // We've lost track of the process, so the best we can do is consider it failed
+                let e = Error::Wait(process.pid, errno);
+                eprintln!("trash: {}", e);
+                process.state = ProcessState::Exited(e.exit_status());
+                break;
+            }
+
+            process.state = process_state(wstatus);
+        }
+    }
+}
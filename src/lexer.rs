@@ -0,0 +1,505 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::parser::{Error, Modifier, ModifierKind, Parameter, RedirectKind, Word, WordPart};
+
+/// Parameters named by a single punctuation character, like `$?`
+const SPECIAL_PARAMETERS: &str = "?$!#@*";
+
+#[derive(Debug)]
+pub enum Token {
+    Word(Word),
+    Semicolon,
+    Newline,
+    Pipe,
+    And,
+    Or,
+    Background,
+    Redirect(i32, RedirectKind),
+}
+
+impl Token {
+    /// How the token appears in error messages
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            Token::Word(_) => "word",
+            Token::Semicolon => ";",
+            Token::Newline => "newline",
+            Token::Pipe => "|",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Background => "&",
+            Token::Redirect(_, RedirectKind::Read) => "<",
+            Token::Redirect(_, RedirectKind::Write) => ">",
+            Token::Redirect(_, RedirectKind::Append) => ">>",
+            Token::Redirect(_, RedirectKind::Duplicate) => ">&",
+            Token::Redirect(_, RedirectKind::WriteAll) => "&>",
+            Token::Redirect(_, RedirectKind::AppendAll) => "&>>",
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    tokens: Vec<Token>,
+    word: Option<Word>,
+}
+
+/// Splits a line of input into words and operators, performing no expansion
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let mut lexer = Lexer::new(input);
+
+    while let Some(c) = lexer.chars.next() {
+        match c {
+            ' ' | '\t' => lexer.finish_word(),
+            '\n' => lexer.push_operator(Token::Newline),
+            ';' => lexer.push_operator(Token::Semicolon),
+            '|' => {
+                let token = if lexer.next_if('|') { Token::Or } else { Token::Pipe };
+                lexer.push_operator(token);
+            },
+            '&' if lexer.chars.peek() == Some(&'&') => {
+                lexer.chars.next();
+                lexer.push_operator(Token::And);
+            },
+            '<' => {
+                let fd = lexer.take_fd_number().unwrap_or(0);
+                let kind = if lexer.next_if('&') { RedirectKind::Duplicate } else { RedirectKind::Read };
+                lexer.push_operator(Token::Redirect(fd, kind));
+            },
+            '>' => {
+                let fd = lexer.take_fd_number().unwrap_or(1);
+                let kind = if lexer.next_if('>') {
+                    RedirectKind::Append
+                } else if lexer.next_if('&') {
+                    RedirectKind::Duplicate
+                } else {
+                    lexer.next_if('|'); // Clobbering is always allowed
+                    RedirectKind::Write
+                };
+                lexer.push_operator(Token::Redirect(fd, kind));
+            },
+            '&' if lexer.chars.peek() == Some(&'>') => {
+                lexer.chars.next();
+                let kind = if lexer.next_if('>') { RedirectKind::AppendAll } else { RedirectKind::WriteAll };
+                lexer.push_operator(Token::Redirect(1, kind));
+            },
+            '&' => lexer.push_operator(Token::Background),
+            // `((expression))` is the same as `let "expression"`, but only where a command can start
+            '(' if lexer.word.is_none() && lexer.chars.peek() == Some(&'(') => {
+                lexer.chars.next();
+                if !lexer.at_command_start() {
+                    return Err(Error::UnexpectedToken("(("));
+                }
+                let expression = lexer.arithmetic()?;
+                lexer.tokens.push(Token::Word(Word { parts: vec![WordPart::Quoted("let".to_string())] }));
+                lexer.tokens.push(Token::Word(Word { parts: vec![WordPart::DoubleQuoted(parse_word(&expression)?.parts)] }));
+            },
+            '#' if lexer.word.is_none() => {
+                // Comment, runs until the end of the line
+                while let Some(&c) = lexer.chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    lexer.chars.next();
+                }
+            },
+            c => lexer.word_char(c)?,
+        }
+    }
+
+    lexer.finish_word();
+    Ok(lexer.tokens)
+}
+
+/// Lexes text that is all one word, like the default in `${NAME:-default}`, where blanks and operators are just characters
+pub fn parse_word(input: &str) -> Result<Word, Error> {
+    let mut lexer = Lexer::new(input);
+    while let Some(c) = lexer.chars.next() {
+        lexer.word_char(c)?;
+    }
+    Ok(lexer.word.take().unwrap_or_default())
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Lexer<'a> {
+        Lexer {
+            chars: input.chars().peekable(),
+            tokens: Vec::with_capacity(16),
+            word: None,
+        }
+    }
+
+    /// Handles a character that's part of a word: quotes, escapes, expansions, or just itself
+    fn word_char(&mut self, c: char) -> Result<(), Error> {
+        match c {
+            '\\' => {
+                match self.chars.next() {
+                    Some('\n') => (), // Line continuation
+                    Some(c) => self.push_part(WordPart::Quoted(c.to_string())),
+                    None => self.push_bare('\\'),
+                }
+            },
+            '\'' => {
+                let mut text = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('\'') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(Error::UnterminatedQuote('\'')),
+                    }
+                }
+                self.push_part(WordPart::Quoted(text));
+            },
+            '"' => {
+                let parts = self.double_quoted()?;
+                self.push_part(WordPart::DoubleQuoted(parts));
+            },
+            '$' => match self.dollar()? {
+                Some(part) => self.push_part(part),
+                None => self.push_bare('$'),
+            },
+            '`' => {
+                let part = self.backquoted(false)?;
+                self.push_part(part);
+            },
+            c => self.push_bare(c),
+        }
+
+        Ok(())
+    }
+
+    /// Reads what follows a `$`. None if it doesn't start an expansion, and so is just a `$`
+    fn dollar(&mut self) -> Result<Option<WordPart>, Error> {
+        match self.chars.peek() {
+            Some(&'{') => {
+                self.chars.next();
+                self.braced_parameter().map(Some)
+            },
+            Some(&'(') => {
+                self.chars.next();
+                if self.next_if('(') {
+                    let expression = self.arithmetic()?;
+                    return Ok(Some(WordPart::Arithmetic(parse_word(&expression)?)));
+                }
+                self.command_substitution().map(Some)
+            },
+            Some(&c) if c == '_' || c.is_ascii_alphanumeric() || SPECIAL_PARAMETERS.contains(c) => {
+                let name = self.name(false);
+                Ok(Some(WordPart::Parameter(Parameter { name, modifier: None })))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads a parameter name: a variable name, a special parameter, or a positional parameter.
+    /// Positional parameters past 9 need braces
+    fn name(&mut self, braced: bool) -> String {
+        match self.chars.peek() {
+            Some(&c) if SPECIAL_PARAMETERS.contains(c) => {
+                self.chars.next();
+                return c.to_string();
+            },
+            Some(&c) if c.is_ascii_digit() => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !c.is_ascii_digit() || (!braced && !name.is_empty()) {
+                        break;
+                    }
+                    name.push(c);
+                    self.chars.next();
+                }
+                return name;
+            },
+            _ => (),
+        }
+
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c != '_' && !c.is_ascii_alphanumeric() {
+                break;
+            }
+            name.push(c);
+            self.chars.next();
+        }
+        name
+    }
+
+    /// Reads the rest of a `${...}`
+    fn braced_parameter(&mut self) -> Result<WordPart, Error> {
+        let name = self.name(true);
+
+        let check_empty = self.next_if(':');
+        let kind = match self.chars.next() {
+            Some('}') if !check_empty && !name.is_empty() => return Ok(WordPart::Parameter(Parameter { name, modifier: None })),
+            Some('}') => return Err(Error::BadSubstitution(format!("${{{}{}}}", name, if check_empty { ":" } else { "" }))),
+            Some('-') => ModifierKind::Default,
+            Some('=') => ModifierKind::Assign,
+            Some('+') => ModifierKind::Alternative,
+            Some('?') => ModifierKind::Error,
+            Some(c) => {
+                let mut text = format!("${{{}{}{}", name, if check_empty { ":" } else { "" }, c);
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(Error::UnterminatedExpansion("${")),
+                    }
+                }
+                text.push('}');
+                return Err(Error::BadSubstitution(text));
+            },
+            None => return Err(Error::UnterminatedExpansion("${")),
+        };
+        if name.is_empty() {
+            return Err(Error::BadSubstitution("${}".to_string()));
+        }
+
+        // Find the closing brace, skipping over any quoted or nested ones
+        let mut text = String::new();
+        let mut depth = 0;
+        let mut quote = None;
+        loop {
+            let c = self.chars.next().ok_or(Error::UnterminatedExpansion("${"))?;
+            match (c, quote) {
+                ('}', None) if depth == 0 => break,
+                ('}', None) => depth -= 1,
+                ('{', None) if text.ends_with('$') => depth += 1,
+                ('\\', q) if q != Some('\'') => {
+                    text.push(c);
+                    if let Some(c) = self.chars.next() {
+                        text.push(c);
+                    }
+                    continue;
+                },
+                ('\'', None) | ('"', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                _ => (),
+            }
+            text.push(c);
+        }
+
+        let word = parse_word(&text)?;
+        Ok(WordPart::Parameter(Parameter { name, modifier: Some(Box::new(Modifier { kind, check_empty, word })) }))
+    }
+
+    /// Reads an arithmetic expression up to and including the `))` that closes it
+    fn arithmetic(&mut self) -> Result<String, Error> {
+        let mut expression = String::new();
+        let mut depth = 0;
+        loop {
+            let c = match self.chars.next() {
+                Some(c) => c,
+                None => return Err(Error::UnterminatedExpansion("((")),
+            };
+
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    if self.next_if(')') {
+                        return Ok(expression);
+                    }
+                    return Err(Error::UnterminatedExpansion("(("));
+                },
+                ')' => depth -= 1,
+                _ => (),
+            }
+            expression.push(c);
+        }
+    }
+
+    /// Reads up to and including the `)` that closes a `$(`, skipping over any quoted or nested parentheses
+    fn command_substitution(&mut self) -> Result<WordPart, Error> {
+        let mut source = String::new();
+        let mut depth = 0;
+        let mut quote = None;
+        loop {
+            let c = match self.chars.next() {
+                Some(c) => c,
+                None => return Err(Error::UnterminatedExpansion("$(")),
+            };
+
+            match (c, quote) {
+                ('\\', Some('\'')) => (),
+                ('\\', _) => {
+                    source.push(c);
+                    match self.chars.next() {
+                        Some(c) => source.push(c),
+                        None => return Err(Error::UnterminatedExpansion("$(")),
+                    }
+                    continue;
+                },
+                (c, Some(q)) if c == q => quote = None,
+                (_, Some(_)) => (),
+                ('\'', None) | ('"', None) | ('`', None) => quote = Some(c),
+                ('(', None) => depth += 1,
+                (')', None) if depth == 0 => break,
+                (')', None) => depth -= 1,
+                _ => (),
+            }
+            source.push(c);
+        }
+
+        Ok(WordPart::CommandSubstitution(source))
+    }
+
+    /// Reads up to and including the closing backtick. Inside, a backslash only escapes `$`, `` ` ``, and `\`,
+    /// and also `"` if the backticks are in double quotes
+    fn backquoted(&mut self, in_double_quotes: bool) -> Result<WordPart, Error> {
+        let mut source = String::new();
+        loop {
+            match self.chars.next() {
+                Some('`') => break,
+                Some('\\') => match self.chars.next() {
+                    Some(c @ '$') | Some(c @ '`') | Some(c @ '\\') => source.push(c),
+                    Some('"') if in_double_quotes => source.push('"'),
+                    Some(c) => {
+                        source.push('\\');
+                        source.push(c);
+                    },
+                    None => return Err(Error::UnterminatedQuote('`')),
+                },
+                Some(c) => source.push(c),
+                None => return Err(Error::UnterminatedQuote('`')),
+            }
+        }
+
+        Ok(WordPart::CommandSubstitution(source))
+    }
+
+    /// Whether the next word would be the first of a command
+    fn at_command_start(&self) -> bool {
+        match self.tokens.last() {
+            None => true,
+            Some(Token::Semicolon) | Some(Token::Newline) | Some(Token::Pipe) | Some(Token::And) | Some(Token::Or) | Some(Token::Background) => true,
+            Some(Token::Word(_)) | Some(Token::Redirect(..)) => false,
+        }
+    }
+
+    fn finish_word(&mut self) {
+        if let Some(word) = self.word.take() {
+            self.tokens.push(Token::Word(word));
+        }
+    }
+
+    fn push_operator(&mut self, token: Token) {
+        self.finish_word();
+        self.tokens.push(token);
+    }
+
+    fn next_if(&mut self, c: char) -> bool {
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes the word being built if it's an unquoted number directly preceding a redirection, like the 2 in `2>`.
+    /// Only plain digits count, so `-1>x` is the argument `-1` with its output sent to x
+    fn take_fd_number(&mut self) -> Option<i32> {
+        let digits = self.word.as_ref()?.bare()?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let fd = digits.parse().ok()?;
+        self.word = None;
+        Some(fd)
+    }
+
+    fn push_bare(&mut self, c: char) {
+        let word = self.word.get_or_insert_with(Word::default);
+        if let Some(WordPart::Bare(ref mut text)) = word.parts.last_mut() {
+            text.push(c);
+        } else {
+            word.parts.push(WordPart::Bare(c.to_string()));
+        }
+    }
+
+    fn push_part(&mut self, part: WordPart) {
+        self.word.get_or_insert_with(Word::default).parts.push(part);
+    }
+
+    /// Reads up to and including the closing `"`
+    fn double_quoted(&mut self) -> Result<Vec<WordPart>, Error> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => break,
+                Some('$') => match self.dollar()? {
+                    Some(part) => {
+                        if !text.is_empty() {
+                            parts.push(WordPart::Quoted(std::mem::take(&mut text)));
+                        }
+                        parts.push(part);
+                    },
+                    None => text.push('$'),
+                },
+                Some('`') => {
+                    if !text.is_empty() {
+                        parts.push(WordPart::Quoted(std::mem::take(&mut text)));
+                    }
+                    parts.push(self.backquoted(true)?);
+                },
+                Some('\\') => {
+                    // Inside double quotes a backslash only escapes characters that are otherwise special
+                    match self.chars.next() {
+                        Some('\n') => (),
+                        Some(c @ '$') | Some(c @ '`') | Some(c @ '"') | Some(c @ '\\') => text.push(c),
+                        Some(c) => {
+                            text.push('\\');
+                            text.push(c);
+                        },
+                        None => return Err(Error::UnterminatedQuote('"')),
+                    }
+                },
+                Some(c) => text.push(c),
+                None => return Err(Error::UnterminatedQuote('"')),
+            }
+        }
+
+        if !text.is_empty() || parts.is_empty() {
+            parts.push(WordPart::Quoted(text));
+        }
+        Ok(parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Words as their text and redirects as `fd>`, `fd<`, and so on
+    fn lex(input: &str) -> Vec<String> {
+        let tokens = match tokenize(input) {
+            Ok(tokens) => tokens,
+            Err(e) => panic!("{:?} failed to lex: {}", input, e),
+        };
+        tokens.iter().map(|token| match *token {
+            Token::Word(ref word) => word.text(),
+            Token::Redirect(fd, _) => format!("{}{}", fd, token.describe()),
+            ref token => token.describe().to_string(),
+        }).collect()
+    }
+
+    #[test]
+    fn fd_numbers() {
+        let cases: &[(&str, &[&str])] = &[
+            ("echo a 2>x", &["echo", "a", "2>", "x"]),
+            ("cat 0<x", &["cat", "0<", "x"]),
+            ("echo a 10>x", &["echo", "a", "10>", "x"]),
+            ("echo a 2>&1", &["echo", "a", "2>&", "1"]),
+            ("echo a -1>x", &["echo", "a", "-1", "1>", "x"]),
+            ("echo b +2>y", &["echo", "b", "+2", "1>", "y"]),
+            ("echo c 1x>y", &["echo", "c", "1x", "1>", "y"]),
+            ("cat -0<x", &["cat", "-0", "0<", "x"]),
+            ("echo d '2'>y", &["echo", "d", "2", "1>", "y"]),
+            ("echo e 99999999999>y", &["echo", "e", "99999999999", "1>", "y"]),
+        ];
+        for &(input, expected) in cases {
+            assert_eq!(lex(input), expected, "{:?}", input);
+        }
+    }
+}
@@ -1,38 +1,141 @@
 #![feature(try_blocks)]
 
+mod arithmetic;
+mod brace;
+mod builtins;
+mod complete;
 mod config;
+mod editor;
+mod error;
+mod exec;
+mod expand;
+mod glob;
+mod history;
+mod jobs;
+mod lexer;
+mod parser;
+mod path;
 mod prompt;
+mod shell;
 
-use std::io::{self, Write};
-use std::ffi::{CString, CStr};
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::ffi::CStr;
 use std::env;
-use std::mem::MaybeUninit;
-use termcolor::{ColorChoice, StandardStream};
-use std::path::Path;
+use std::fs::File;
+use termcolor::{BufferWriter, ColorChoice};
+use editor::Editor;
+use shell::Shell;
 
-fn main() {
-    let stdout = StandardStream::stdout(ColorChoice::Auto);
-    let mut handle = stdout.lock();
-    let mut input_line = String::with_capacity(256);
-    let mut argv = Vec::with_capacity(16);
-    let mut exit_status = 0;
+const USAGE: &str = "usage: trash [file [args...]] | -c command [name [args...]] | -s [args...]";
+
+enum Script {
+    File(String),
+    /// Given with `-c`
+    Command(String),
+}
+
+/// What to run, picked by the arguments trash is run with
+struct Invocation {
+    /// None to read commands from stdin
+    script: Option<Script>,
+    /// `$0`, if it isn't the name trash was run as
+    name: Option<String>,
+    positional: Vec<String>,
+}
+
+/// Runs the file named by the first argument, with the rest as positional parameters
+fn script_file(mut args: Vec<String>) -> Invocation {
+    if args.is_empty() {
+        return Invocation { script: None, name: None, positional: Vec::new() };
+    }
+
+    let path = args.remove(0);
+    Invocation { script: Some(Script::File(path.clone())), name: Some(path), positional: args }
+}
+
+fn parse_args(mut args: Vec<String>) -> Result<Invocation, String> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("-c") => {
+            if args.len() < 2 {
+                return Err("-c requires an argument".to_string());
+            }
+            let mut args = args.split_off(1);
+            let command = args.remove(0);
+            let name = if args.is_empty() { None } else { Some(args.remove(0)) };
+            Ok(Invocation { script: Some(Script::Command(command)), name, positional: args })
+        },
+        Some("-s") => Ok(Invocation { script: None, name: None, positional: args.split_off(1) }),
+        // Whatever comes after `--` is a file name, even if it starts with `-`
+        Some("--") => Ok(script_file(args.split_off(1))),
+        Some(flag) if flag.starts_with('-') && flag.len() > 1 => Err(format!("{}: invalid option", flag)),
+        _ => Ok(script_file(args)),
+    }
+}
+
+/// Runs commands from a script until the end, or until there's a syntax error.
+/// Lines are read until they make up complete commands, so quotes and the like can span lines
+fn run_script(shell: &mut Shell, mut input: Box<dyn BufRead>) {
+    let mut source = String::new();
+    loop {
+        let read = match input.read_line(&mut source) {
+            Ok(read) => read,
+            Err(e) => {
+                eprintln!("trash: Error reading script: {}", e);
+                shell.exit_status = 1;
+                return;
+            }
+        };
+
+        // An odd number of backslashes at the end of a line continues it onto the next
+        let trailing_backslashes = source.trim_end_matches('\n').chars().rev().take_while(|&c| c == '\\').count();
+        if read > 0 && trailing_backslashes % 2 == 1 {
+            continue;
+        }
+
+        let list = match parser::parse(&source, &shell.aliases) {
+            Ok(list) => list,
+            Err(ref e) if read > 0 && e.is_incomplete() => continue,
+            Err(e) => {
+                eprintln!("trash: {}", e);
+                shell.exit_status = 2;
+                shell.exit_signal = None;
+                return;
+            }
+        };
+        source.clear();
+
+        exec::run_list(shell, &list);
+        shell.jobs.reap();
+        shell.jobs.take_finished();
 
-    // Mask out some signals
-    /* unsafe {
-        let mut signals: libc::sigset_t = std::mem::uninitialized();
-        libc::sigemptyset(&mut signals);
-        libc::sigaddset(&mut signals, libc::SIGABRT);
-        libc::sigprocmask(libc::SIG_SETMASK, &signals, std::ptr::null_mut());
-    } */
+        if read == 0 {
+            return;
+        }
+    }
+}
+
+fn main() {
+    let invocation = match parse_args(env::args().skip(1).collect()) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("trash: {}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
 
     let user_id = unsafe { libc::getuid() };
     let (home_dir, user_name) = unsafe {
-        let pwid_ptr = libc::getpwuid(user_id);
+        let pwid_ptr = loop {
+            *libc::__errno_location() = 0;
+            let pwid_ptr = libc::getpwuid(user_id);
+            if !pwid_ptr.is_null() || *libc::__errno_location() != libc::EINTR {
+                break pwid_ptr;
+            }
+        };
 
         if pwid_ptr.is_null() {
-            match { *libc::__errno_location() } {
+            match *libc::__errno_location() {
                 libc::EIO => eprintln!("I/O error occurred while trying to access user information"),
-                libc::EINTR => eprintln!("Signal caught while trying to access user information"), // @Robustness do we handle this?
                 libc::EMFILE => eprintln!("Have no more file descriptors available; can't access user information"),
                 _ => eprintln!("Unknown error occurred while trying to access user information")
             }
@@ -62,183 +165,122 @@ fn main() {
         (home_dir, user_name)
     };
 
-    let (path_list, owned_exports, aliases) = match config::load_settings(&home_dir) {
-        Ok((path, owned_exports, aliases)) => (path, owned_exports, aliases),
+    let settings = match config::load_settings(&home_dir) {
+        Ok(settings) => settings,
         Err(e) => {
             eprintln!("{}", e);
             std::process::exit(-1);
         }
     };
 
-    let mut exports: Vec<*const i8> = owned_exports.iter().map(|c_string| c_string.as_ptr()).collect();
-    exports.push(std::ptr::null_mut());
+    let mut shell = Shell::new(home_dir, settings);
+    if let Some(name) = invocation.name {
+        shell.name = name;
+    }
+    shell.positional = invocation.positional;
+    jobs::install_sigchld_handler();
+
+    // A prompt is only shown when commands are being typed in
+    let script: Option<Box<dyn BufRead>> = match invocation.script {
+        Some(Script::File(path)) => match File::open(&path) {
+            Ok(file) => Some(Box::new(BufReader::new(file))),
+            Err(e) => {
+                eprintln!("trash: {}: {}", path, error::errno_description(e.raw_os_error().unwrap_or(0)));
+                std::process::exit(127);
+            }
+        },
+        Some(Script::Command(command)) => Some(Box::new(Cursor::new(command.into_bytes()))),
+        None if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 => Some(Box::new(BufReader::new(io::stdin()))),
+        None => None,
+    };
+
+    match script {
+        Some(script) => run_script(&mut shell, script),
+        None => run_prompt(&mut shell, &user_name, user_id),
+    }
+
+    let _ = io::stdout().flush();
+    std::process::exit(shell.exit_status);
+}
 
-    let mut spawn_file_actions: MaybeUninit<libc::posix_spawn_file_actions_t> = MaybeUninit::uninit();
-    let mut spawn_attributes: MaybeUninit<libc::posix_spawnattr_t> = MaybeUninit::uninit();
+/// Reads commands typed at the terminal, showing a prompt for each
+fn run_prompt(shell: &mut Shell, user_name: &str, user_id: libc::uid_t) {
+    let stdout = BufferWriter::stdout(ColorChoice::Auto);
+    let interactive = editor::is_terminal();
+    let mut editor = Editor::default();
+    let mut input_line = String::with_capacity(256);
 
-    unsafe {
-        libc::posix_spawn_file_actions_init(spawn_file_actions.as_mut_ptr());
-        libc::posix_spawnattr_init(spawn_attributes.as_mut_ptr());
+    match history::History::load(&shell.home_dir) {
+        Ok(history) => shell.history = history,
+        Err(e) => eprintln!("trash: Couldn't load history, it won't be saved: {}", e),
     }
+    jobs::init_job_control(shell);
 
     loop {
         input_line.clear();
 
-        // IO: print out, get input in
-        let result: Result<(), io::Error> = try {
-            prompt::write_prompt(&mut handle, &user_name, user_id, &home_dir, exit_status)?;
-            handle.flush()?;
-            io::stdin().read_line(&mut input_line)?;
-            let _ = input_line.pop(); // Newline
-        };
-
-        if let Err(e) = result {
-            eprintln!("Error performing shell I/O: {:?}", e);
-            break;
+        // Let the user know about any background jobs that finished while they were busy
+        shell.jobs.reap();
+        for job in shell.jobs.take_finished() {
+            eprintln!("[{}] {:<24}{}", job.id, job.state_description(shell.options.pipefail), job.command);
         }
 
-        for statement in input_line.split(';') {
-            argv.clear();
-            let mut statement = statement.trim().to_string();
-
-            if statement.is_empty() {
-                continue;
-            }
-
-            let do_aliases = if statement.starts_with('\\') {
-                statement.remove(0);
-                false
+        // IO: print out, get input in
+        let result: Result<bool, io::Error> = try {
+            let mut prompt = stdout.buffer();
+            prompt::write_prompt(&mut prompt, user_name, user_id, &shell.home_dir, shell.exit_status, shell.exit_signal)?;
+            if interactive {
+                editor.read_line(&mut input_line, &String::from_utf8_lossy(prompt.as_slice()), &shell.history.entries, &mut |text| complete::complete(shell, text))?
             } else {
-                true
-            };
-
-
-            {
-                let command: Vec<&str> = statement.split_whitespace().collect();
-
-                // Bultin check
-                match command[0] {
-                    "cd" => {
-                        let result: Result<(), _> = if command.len() > 2 {
-                            eprintln!("cd: Expected 0 or 1 arguments, got {}", command.len() - 1);
-                            exit_status = 1;
-                            Ok(())
-                        } else if command.len() == 1 {
-                            env::set_current_dir(Path::new(&home_dir))
-                        } else {
-                            env::set_current_dir(Path::new(command[1]))
-                        };
-
-                        if let Err(e) = result {
-                            eprintln!("cd: {}", e);
-                            exit_status = 1;
-                        }
-
-                        continue;
-                    },
-                    _ => ()
-                }
+                stdout.print(&prompt)?;
+                io::stdout().flush()?;
+                io::stdin().read_line(&mut input_line)? > 0
             }
+        };
 
-            // Not a builtin, proceed to split it up for fork+exec
-            statement.push('\0'); // Needed because libc expects null termined arguments
-
-            // split up to command args, etc
-            unsafe {
-                argv.push(statement.as_ptr());
-                let statement_bytes = statement.as_bytes_mut();
-                for byte in statement_bytes.iter_mut() {
-                    if *byte == b' ' {
-                        *byte = 0;
-                        argv.push((byte as *const u8).offset(1));
-                    }
-                }
-                argv.push(std::ptr::null());
+        match result {
+            Ok(false) => break, // EOF
+            Ok(true) => (),
+            Err(e) => {
+                eprintln!("Error performing shell I/O: {:?}", e);
+                break;
             }
+        }
 
-            {
-                let mut no_access = false;
-                let mut success = false;
-
-                let mut binary_name = unsafe { CStr::from_ptr(argv[0] as *const i8) };
-
-                // Alias handling
-                if do_aliases {
-                    if let Some(replacement) = aliases.get(binary_name) {
-                        argv[0] = replacement.as_ptr();
-                        for token in replacement.split('\0').skip(1).filter(|x| !x.is_empty()) {
-                            argv.insert(1, token.as_ptr())
-                        }
-                        binary_name = unsafe { CStr::from_ptr(replacement.as_ptr() as *const i8) };
-                    }
-                }
-
-                // Path lookup + execution
-                for path in path_list.iter() {
-                    let mut temp_path = path.clone();
-                    temp_path.push(binary_name.to_str().unwrap());
-                    {
-                        let full_path = CString::new(temp_path.to_str().unwrap()).unwrap();
-                        unsafe { *libc::__errno_location() = 0 };
-                        // Spawn
-                        {
-                            let mut pid: libc::pid_t = 0;
-                            let ret_val = unsafe { libc::posix_spawn(&mut pid as *mut libc::pid_t, full_path.as_ptr(), std::ptr::null(), std::ptr::null(), argv.as_ptr() as *const *mut i8, exports.as_ptr() as *const *mut i8) };
-
-                            match ret_val {
-                                0 => (),
-                                libc::EAGAIN => {
-                                    eprintln!("Can't allocate resources to fork");
-                                    std::process::exit(-1);
-                                },
-                                libc::ENOMEM => {
-                                    eprintln!("Can't allocate memory to fork");
-                                    std::process::exit(-1);
-                                },
-                                libc::ENOSYS => {
-                                    eprintln!("posix_spawn unsupported on this platform");
-                                    std::process::exit(-1);
-                                },
-                                x => {
-                                    eprintln!("Unknown error occurred while trying to spawn child process: {}", x);
-                                    std::process::exit(-1);
-                                }
-                            }
-                        }
-
-                        // Wait for our child to finish
-                        let mut wstatus: i32 = 0;
-                        {
-                            let wait_ret_val = unsafe { libc::wait(&mut wstatus as *mut i32) };
-                            if wait_ret_val == -1 {
-                                match unsafe { *libc::__errno_location() } {
-                                    libc::ECHILD => eprintln!("Somehow, no child process to wait for"),
-                                    libc::EINTR => eprintln!("Signal caught while waiting for child process"), // @Robustness do we handle this?
-                                    _ => eprintln!("Unknown error occurred while trying to wait for child process")
-                                }
-
-                                std::process::exit(-1);
-                            }
-                        }
-
-                        if unsafe { *libc::__errno_location() == 0 } {
-                            success = true;
-                            exit_status = libc::WEXITSTATUS(wstatus);
-                            break;
-                        } else if unsafe { *libc::__errno_location() } == libc::EACCES {
-                            no_access = true;
-                        }
-                    }
+        if interactive && shell.options.histexpand {
+            match history::expand(&input_line, &shell.history.entries) {
+                Ok(Some(expanded)) => {
+                    // Show what's actually going to run
+                    print!("{}", expanded);
+                    input_line = expanded;
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("trash: {}", e);
+                    shell.exit_status = 1;
+                    shell.exit_signal = None;
+                    continue;
                 }
+            }
+        }
 
-                if !success && no_access {
-                    eprintln!("Found matching item for {:?} on path, but couldn't execute it", binary_name);
-                    exit_status = 126;
-                } else if !success {
-                    eprintln!("Command not found {:?}.", binary_name);
-                    exit_status = 127;
-                }
+        // Only what's typed at a terminal is worth remembering
+        if interactive {
+            if let Err(e) = shell.history.add(input_line.trim_end_matches('\n'), &shell.options) {
+                eprintln!("trash: Couldn't save history: {}", e);
             }
         }
+
+        let list = match parser::parse(&input_line, &shell.aliases) {
+            Ok(list) => list,
+            Err(e) => {
+                eprintln!("trash: {}", e);
+                shell.exit_status = 2;
+                shell.exit_signal = None;
+                continue;
+            }
+        };
+
+        exec::run_list(shell, &list);
     }
 }
@@ -0,0 +1,650 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::lexer::{self, Token};
+
+pub enum Error {
+    UnterminatedQuote(char),
+    UnexpectedToken(&'static str),
+    UnexpectedEnd,
+    BadAlias(String),
+    /// Holds what it was that wasn't closed, like `${`
+    UnterminatedExpansion(&'static str),
+    BadSubstitution(String),
+}
+
+impl Error {
+    /// Whether more input could finish what was started, like an open quote
+    pub fn is_incomplete(&self) -> bool {
+        matches!(*self, Error::UnterminatedQuote(_) | Error::UnexpectedEnd | Error::UnterminatedExpansion(_))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match *self {
+            Error::UnterminatedQuote(c) => write!(f, "syntax error: unterminated {} quote", c),
+            Error::UnexpectedToken(token) => write!(f, "syntax error near unexpected token `{}`", token),
+            Error::UnexpectedEnd => write!(f, "syntax error: unexpected end of input"),
+            Error::BadAlias(ref name) => write!(f, "alias {} expands to something that isn't a command", name),
+            Error::UnterminatedExpansion(opening) => write!(f, "syntax error: unterminated {}", opening),
+            Error::BadSubstitution(ref text) => write!(f, "{}: bad substitution", text),
+        }
+    }
+}
+
+/// One piece of a word, remembering how it was quoted
+#[derive(Debug, Clone)]
+pub enum WordPart {
+    Bare(String),
+    Quoted(String),
+    DoubleQuoted(Vec<WordPart>),
+    Parameter(Parameter),
+    /// `$(...)` or backticks, holding the commands inside to be parsed when they run
+    CommandSubstitution(String),
+    /// `$((...))`, holding the expression, which gets expanded before it's evaluated
+    Arithmetic(Word),
+}
+
+/// What to do when a parameter is unset, the `-` in `${NAME:-default}`
+#[derive(Debug, Clone, Copy)]
+pub enum ModifierKind {
+    /// `-`, use the word instead
+    Default,
+    /// `=`, assign the word to the parameter and use it
+    Assign,
+    /// `+`, use the word only if the parameter is set
+    Alternative,
+    /// `?`, fail with the word as the message
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Modifier {
+    pub kind: ModifierKind,
+    /// Written with a `:`, so an empty value counts as unset
+    pub check_empty: bool,
+    pub word: Word,
+}
+
+/// `$NAME` or `${NAME}`, possibly with a modifier
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub modifier: Option<Box<Modifier>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RedirectKind {
+    Read,
+    Write,
+    Append,
+    /// `>&` or `<&`, the target is a file descriptor or `-` to close
+    Duplicate,
+    /// `&>`, both stdout and stderr go to the target
+    WriteAll,
+    AppendAll,
+}
+
+#[derive(Debug)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(Debug)]
+pub struct Command {
+    /// `NAME=value` words in front of the command
+    pub assignments: Vec<(String, Word)>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// Commands connected by `|`, each one's stdout feeding the next one's stdin
+#[derive(Debug)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// Pipelines joined by `&&` and `||`, each one only running depending on the status of the ones before it
+#[derive(Debug)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// Ended with `&`, so the shell doesn't wait for it
+    pub background: bool,
+}
+
+/// And-or lists separated by `;`, `&`, or newlines
+#[derive(Debug, Default)]
+pub struct List {
+    pub and_ors: Vec<AndOr>,
+}
+
+impl Word {
+    /// The word's text after quote removal
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        push_text(&self.parts, &mut text);
+        text
+    }
+
+    /// Whether expanding the word runs any commands
+    pub fn has_command_substitution(&self) -> bool {
+        has_command_substitution(&self.parts)
+    }
+
+    /// The word's text, but only if no part of it was quoted or escaped
+    pub fn bare(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Bare(text)] => Some(text),
+            _ => None,
+        }
+    }
+}
+
+fn has_command_substitution(parts: &[WordPart]) -> bool {
+    parts.iter().any(|part| match *part {
+        WordPart::CommandSubstitution(_) => true,
+        WordPart::Arithmetic(ref expression) => expression.has_command_substitution(),
+        WordPart::DoubleQuoted(ref inner) => has_command_substitution(inner),
+        WordPart::Parameter(Parameter { modifier: Some(ref modifier), .. }) => modifier.word.has_command_substitution(),
+        _ => false,
+    })
+}
+
+fn push_text(parts: &[WordPart], text: &mut String) {
+    for part in parts {
+        match *part {
+            WordPart::Bare(ref s) | WordPart::Quoted(ref s) => text.push_str(s),
+            WordPart::DoubleQuoted(ref inner) => push_text(inner, text),
+            WordPart::Parameter(ref parameter) => text.push_str(&parameter.to_string()),
+            WordPart::CommandSubstitution(ref source) => {
+                text.push_str("$(");
+                text.push_str(source);
+                text.push(')');
+            },
+            WordPart::Arithmetic(ref expression) => {
+                text.push_str("$((");
+                push_text(&expression.parts, text);
+                text.push_str("))");
+            },
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.modifier {
+            None => write!(f, "${{{}}}", self.name),
+            Some(ref modifier) => {
+                let operator = match modifier.kind {
+                    ModifierKind::Default => '-',
+                    ModifierKind::Assign => '=',
+                    ModifierKind::Alternative => '+',
+                    ModifierKind::Error => '?',
+                };
+                write!(f, "${{{}{}{}{}}}", self.name, if modifier.check_empty { ":" } else { "" }, operator, modifier.word)
+            },
+        }
+    }
+}
+
+/// Whether the text can be a variable name
+pub fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => chars.all(|c| c == '_' || c.is_ascii_alphanumeric()),
+        _ => false,
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_parts(&self.parts, false, f)
+    }
+}
+
+/// Writes the parts back out, quoted so that they'd parse the same way again
+fn write_parts(parts: &[WordPart], in_double_quotes: bool, f: &mut fmt::Formatter) -> fmt::Result {
+    for part in parts {
+        match *part {
+            WordPart::Bare(ref text) => write!(f, "{}", text)?,
+            WordPart::Quoted(ref text) if in_double_quotes => {
+                for c in text.chars() {
+                    if let '"' | '\\' | '$' | '`' = c {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+            },
+            WordPart::Quoted(ref text) => write!(f, "'{}'", text.replace('\'', "'\\''"))?,
+            WordPart::DoubleQuoted(ref inner) => {
+                write!(f, "\"")?;
+                write_parts(inner, true, f)?;
+                write!(f, "\"")?;
+            },
+            WordPart::Parameter(ref parameter) => write!(f, "{}", parameter)?,
+            WordPart::CommandSubstitution(ref source) => write!(f, "$({})", source)?,
+            WordPart::Arithmetic(ref expression) => write!(f, "$(({}))", expression)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for (name, value) in self.assignments.iter() {
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            write!(f, "{}={}", name, value)?;
+        }
+        for word in self.words.iter() {
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            write!(f, "{}", word)?;
+        }
+        for redirect in self.redirects.iter() {
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            let (operator, default_fd) = match redirect.kind {
+                RedirectKind::Read => ("<", 0),
+                RedirectKind::Write => (">", 1),
+                RedirectKind::Append => (">>", 1),
+                RedirectKind::Duplicate if redirect.fd == 0 => ("<&", 0),
+                RedirectKind::Duplicate => (">&", 1),
+                RedirectKind::WriteAll => ("&>", 1),
+                RedirectKind::AppendAll => ("&>>", 1),
+            };
+            if redirect.fd != default_fd {
+                write!(f, "{}", redirect.fd)?;
+            }
+            write!(f, "{}{}", operator, redirect.target)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in self.rest.iter() {
+            match connector {
+                Connector::And => write!(f, " && {}", pipeline)?,
+                Connector::Or => write!(f, " || {}", pipeline)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits a word like `NAME=value` into the name and the value, if it is an assignment
+fn assignment(word: &Word) -> Option<(String, Word)> {
+    let text = match word.parts.first() {
+        Some(WordPart::Bare(text)) => text,
+        _ => return None,
+    };
+
+    let (name, value) = text.split_once('=')?;
+    if !is_name(name) {
+        return None;
+    }
+
+    let mut parts = Vec::with_capacity(word.parts.len());
+    if !value.is_empty() {
+        parts.push(WordPart::Bare(value.to_string()));
+    }
+    parts.extend(word.parts[1..].iter().cloned());
+    Some((name.to_string(), Word { parts }))
+}
+
+struct Parser<'a> {
+    tokens: VecDeque<Token>,
+    aliases: &'a HashMap<String, String>,
+}
+
+/// Parses a line of input, expanding any aliases in command position
+pub fn parse(input: &str, aliases: &HashMap<String, String>) -> Result<List, Error> {
+    let mut parser = Parser {
+        tokens: lexer::tokenize(input)?.into(),
+        aliases,
+    };
+
+    parser.list()
+}
+
+impl<'a> Parser<'a> {
+    fn list(&mut self) -> Result<List, Error> {
+        let mut list = List::default();
+
+        loop {
+            match self.tokens.pop_front() {
+                None => break,
+                Some(Token::Newline) => continue,
+                Some(token @ Token::Word(_)) | Some(token @ Token::Redirect(..)) => {
+                    self.tokens.push_front(token);
+                    let mut and_or = self.and_or()?;
+                    match self.tokens.pop_front() {
+                        None | Some(Token::Newline) | Some(Token::Semicolon) => (),
+                        Some(Token::Background) => and_or.background = true,
+                        Some(_) => unreachable!(),
+                    }
+                    list.and_ors.push(and_or);
+                },
+                Some(token) => return Err(Error::UnexpectedToken(token.describe())),
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, Error> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let connector = match self.tokens.front() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.tokens.pop_front();
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+
+        Ok(AndOr { first, rest, background: false })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, Error> {
+        let mut commands = vec![self.command()?];
+
+        while let Some(Token::Pipe) = self.tokens.front() {
+            self.tokens.pop_front();
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+
+        Ok(Pipeline { commands })
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token::Newline) = self.tokens.front() {
+            self.tokens.pop_front();
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, Error> {
+        match self.tokens.front() {
+            Some(Token::Word(_)) | Some(Token::Redirect(..)) => (),
+            Some(token) => return Err(Error::UnexpectedToken(token.describe())),
+            None => return Err(Error::UnexpectedEnd),
+        }
+
+        self.expand_aliases()?;
+
+        let mut assignments = Vec::new();
+        let mut words = Vec::with_capacity(8);
+        let mut redirects = Vec::new();
+        loop {
+            match self.tokens.pop_front() {
+                Some(Token::Word(word)) => match assignment(&word) {
+                    Some(assignment) if words.is_empty() => assignments.push(assignment),
+                    _ => words.push(word),
+                },
+                Some(Token::Redirect(fd, kind)) => match self.tokens.pop_front() {
+                    Some(Token::Word(target)) => redirects.push(Redirect { fd, kind, target }),
+                    Some(token) => return Err(Error::UnexpectedToken(token.describe())),
+                    None => return Err(Error::UnexpectedEnd),
+                },
+                Some(token) => {
+                    self.tokens.push_front(token);
+                    break;
+                },
+                None => break,
+            }
+        }
+
+        Ok(Command { assignments, words, redirects })
+    }
+
+    /// Replaces the word at the front of the token stream with its alias, repeatedly, until no alias applies
+    fn expand_aliases(&mut self) -> Result<(), Error> {
+        let mut expanded = HashSet::new();
+
+        loop {
+            let name = match self.tokens.front() {
+                Some(Token::Word(word)) => match word.bare() {
+                    Some(name) if !expanded.contains(name) => name.to_string(),
+                    _ => return Ok(()),
+                },
+                _ => return Ok(()),
+            };
+
+            let replacement_tokens = match self.aliases.get(&name) {
+                Some(replacement) => lexer::tokenize(replacement)?,
+                None => return Ok(()),
+            };
+
+            match replacement_tokens.first() {
+                Some(Token::Word(_)) => (),
+                _ => return Err(Error::BadAlias(name)),
+            }
+            expanded.insert(name);
+
+            self.tokens.pop_front();
+            for token in replacement_tokens.into_iter().rev() {
+                self.tokens.push_front(token);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with(input: &str, aliases: &HashMap<String, String>) -> List {
+        match parse(input, aliases) {
+            Ok(list) => list,
+            Err(e) => panic!("{:?} failed to parse: {}", input, e),
+        }
+    }
+
+    fn parse_ok(input: &str) -> List {
+        parse_with(input, &HashMap::new())
+    }
+
+    fn parse_err(input: &str) -> Error {
+        match parse(input, &HashMap::new()) {
+            Ok(_) => panic!("{:?} parsed when it shouldn't have", input),
+            Err(e) => e,
+        }
+    }
+
+    /// The only command of the only pipeline
+    fn command(list: &List) -> &Command {
+        assert_eq!(list.and_ors.len(), 1);
+        assert!(list.and_ors[0].rest.is_empty());
+        assert_eq!(list.and_ors[0].first.commands.len(), 1);
+        &list.and_ors[0].first.commands[0]
+    }
+
+    fn words(command: &Command) -> Vec<String> {
+        command.words.iter().map(|word| word.text()).collect()
+    }
+
+    #[test]
+    fn quoting() {
+        let list = parse_ok(r#"echo 'a  b' "c $X" d\ e 'it'\''s' "q\"\\" "\n""#);
+        assert_eq!(words(command(&list)), ["echo", "a  b", "c ${X}", "d e", "it's", "q\"\\", "\\n"]);
+
+        let list = parse_ok("echo 'a' b");
+        let command = command(&list);
+        assert!(matches!(command.words[1].parts.as_slice(), [WordPart::Quoted(_)]));
+        assert_eq!(command.words[2].bare(), Some("b"));
+    }
+
+    #[test]
+    fn comments_and_continuations() {
+        assert_eq!(words(command(&parse_ok("echo a # not b"))), ["echo", "a"]);
+        assert_eq!(words(command(&parse_ok("echo a#b"))), ["echo", "a#b"]);
+        assert_eq!(words(command(&parse_ok("echo a\\\nb"))), ["echo", "ab"]);
+        assert!(parse_ok("# only a comment").and_ors.is_empty());
+    }
+
+    #[test]
+    fn redirects() {
+        let list = parse_ok("cmd 2>&1 >out >>log <in 3<&- &>all &>>both 2>|err");
+        let redirects: Vec<(i32, String, String)> = command(&list).redirects.iter()
+            .map(|redirect| (redirect.fd, format!("{:?}", redirect.kind), redirect.target.text()))
+            .collect();
+        assert_eq!(redirects, [
+            (2, "Duplicate".to_string(), "1".to_string()),
+            (1, "Write".to_string(), "out".to_string()),
+            (1, "Append".to_string(), "log".to_string()),
+            (0, "Read".to_string(), "in".to_string()),
+            (3, "Duplicate".to_string(), "-".to_string()),
+            (1, "WriteAll".to_string(), "all".to_string()),
+            (1, "AppendAll".to_string(), "both".to_string()),
+            (2, "Write".to_string(), "err".to_string()),
+        ]);
+        assert_eq!(words(command(&list)), ["cmd"]);
+
+        // Only digits right up against the operator are a file descriptor
+        let list = parse_ok("echo 2 >x a2>y");
+        assert_eq!(words(command(&list)), ["echo", "2", "a2"]);
+        assert_eq!(command(&list).redirects.iter().map(|redirect| redirect.fd).collect::<Vec<_>>(), [1, 1]);
+    }
+
+    #[test]
+    fn lists_and_pipelines() {
+        let list = parse_ok("a | b | c && d || e & f; g\nh");
+        assert_eq!(list.and_ors.len(), 4);
+
+        let first = &list.and_ors[0];
+        assert!(first.background);
+        assert_eq!(first.first.commands.len(), 3);
+        assert!(matches!(first.rest.as_slice(), [(Connector::And, _), (Connector::Or, _)]));
+        assert_eq!(first.to_string(), "a | b | c && d || e");
+
+        assert!(list.and_ors[1..].iter().all(|and_or| !and_or.background && and_or.rest.is_empty()));
+
+        // A line can end after an operator that needs more
+        let list = parse_ok("a &&\nb |\nc");
+        assert_eq!(list.and_ors.len(), 1);
+        assert_eq!(list.and_ors[0].rest[0].1.commands.len(), 2);
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases: HashMap<String, String> = [
+            ("ll", "ls -l"),
+            ("greet", "echo hi; echo there"),
+            ("ls", "ls --color"),
+            ("a", "b x"),
+            ("b", "a y"),
+        ].iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+        assert_eq!(words(command(&parse_with("ll foo", &aliases))), ["ls", "--color", "-l", "foo"]);
+        assert_eq!(parse_with("greet; greet", &aliases).and_ors.len(), 4);
+
+        // Only in command position, and not when quoted
+        assert_eq!(words(command(&parse_with("echo ll", &aliases))), ["echo", "ll"]);
+        assert_eq!(words(command(&parse_with("\\ll", &aliases))), ["ll"]);
+        assert_eq!(parse_with("true && ll", &aliases).and_ors[0].rest[0].1.commands[0].words[0].text(), "ls");
+
+        // An alias isn't expanded again inside its own expansion
+        assert_eq!(words(command(&parse_with("a", &aliases))), ["a", "y", "x"]);
+    }
+
+    #[test]
+    fn assignments() {
+        let list = parse_ok("A=1 B=\"x y\" C= cmd D=2");
+        let command = command(&list);
+        let assignments: Vec<(String, String)> = command.assignments.iter().map(|(name, value)| (name.clone(), value.text())).collect();
+        assert_eq!(assignments, [("A".to_string(), "1".to_string()), ("B".to_string(), "x y".to_string()), ("C".to_string(), String::new())]);
+        assert_eq!(words(command), ["cmd", "D=2"]);
+        assert_eq!(command.to_string(), "A=1 B=\"x y\" C= cmd D=2");
+
+        // Not a valid name, so just a word
+        assert_eq!(words(super::tests::command(&parse_ok("1A=x"))), ["1A=x"]);
+        assert!(super::tests::command(&parse_ok("X=1")).words.is_empty());
+    }
+
+    #[test]
+    fn expansions() {
+        let list = parse_ok("echo ${X:-a b} $? ${10} $(a $(b) \")\") `c` $((1 + (2)))");
+        let command = command(&list);
+        match command.words[1].parts.as_slice() {
+            [WordPart::Parameter(Parameter { name, modifier: Some(modifier) })] => {
+                assert_eq!(name, "X");
+                assert!(modifier.check_empty);
+                assert!(matches!(modifier.kind, ModifierKind::Default));
+                assert_eq!(modifier.word.text(), "a b");
+            },
+            parts => panic!("unexpected parts {:?}", parts),
+        }
+        assert!(matches!(command.words[2].parts.as_slice(), [WordPart::Parameter(Parameter { name, modifier: None })] if name == "?"));
+        assert!(matches!(command.words[3].parts.as_slice(), [WordPart::Parameter(Parameter { name, .. })] if name == "10"));
+        assert!(matches!(command.words[4].parts.as_slice(), [WordPart::CommandSubstitution(source)] if source == "a $(b) \")\""));
+        assert!(matches!(command.words[5].parts.as_slice(), [WordPart::CommandSubstitution(source)] if source == "c"));
+        assert!(matches!(command.words[6].parts.as_slice(), [WordPart::Arithmetic(expression)] if expression.text() == "1 + (2)"));
+
+        // Not expansions at all
+        assert_eq!(words(super::tests::command(&parse_ok("echo $ a$ '$X'"))), ["echo", "$", "a$", "$X"]);
+    }
+
+    #[test]
+    fn arithmetic_command() {
+        assert_eq!(words(command(&parse_ok("((x = 1 + 2))"))), ["let", "x = 1 + 2"]);
+        assert_eq!(parse_ok("true && ((x++)); ((y))").and_ors.len(), 2);
+        assert!(matches!(parse_err("echo a ((1))"), Error::UnexpectedToken("((")));
+    }
+
+    #[test]
+    fn errors() {
+        // Things more input could finish
+        for input in ["echo 'abc", "echo \"abc", "echo `abc", "a &&", "a |", "echo ${X", "echo $(a", "echo $((1 + 2"] {
+            assert!(parse_err(input).is_incomplete(), "{:?} should be incomplete", input);
+        }
+
+        // Things it couldn't
+        for input in ["| a", "a && && b", "; a", "echo ${X%}", "echo ${}", "a > |"] {
+            assert!(!parse_err(input).is_incomplete(), "{:?} shouldn't be incomplete", input);
+        }
+        assert!(matches!(parse_err("a ;; b"), Error::UnexpectedToken(";")));
+        assert!(matches!(parse_err("echo ${X%}"), Error::BadSubstitution(_)));
+    }
+}
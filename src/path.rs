@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+pub enum Resolution {
+    Found(PathBuf),
+    /// Something by that name exists, but we aren't allowed to execute it
+    NotExecutable(PathBuf),
+    NotFound,
+}
+
+/// Whether the file exists, and if so, whether it's something we can execute
+fn check(path: &Path) -> Option<bool> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if metadata.is_dir() {
+        return Some(false);
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    Some(unsafe { libc::access(c_path.as_ptr(), libc::X_OK) } == 0)
+}
+
+/// Finds the program a command name refers to. Names containing a `/` are used as is, anything else is searched for in `path_list`
+pub fn resolve(name: &str, path_list: &[PathBuf]) -> Resolution {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return match check(&path) {
+            Some(true) => Resolution::Found(path),
+            Some(false) => Resolution::NotExecutable(path),
+            None => Resolution::NotFound,
+        };
+    }
+
+    let mut not_executable = None;
+    for directory in path_list.iter() {
+        // An empty entry means the current directory
+        let mut candidate = if directory.as_os_str().is_empty() { PathBuf::from(".") } else { directory.clone() };
+        candidate.push(name);
+
+        match check(&candidate) {
+            Some(true) => return Resolution::Found(candidate),
+            Some(false) if not_executable.is_none() => not_executable = Some(candidate),
+            _ => (),
+        }
+    }
+
+    match not_executable {
+        Some(path) => Resolution::NotExecutable(path),
+        None => Resolution::NotFound,
+    }
+}
+
+/// Every executable in `path_list` with the given name, in the order they'd be found
+pub fn resolve_all(name: &str, path_list: &[PathBuf]) -> Vec<PathBuf> {
+    if name.contains('/') {
+        return match resolve(name, path_list) {
+            Resolution::Found(path) => vec![path],
+            _ => Vec::new(),
+        };
+    }
+
+    path_list.iter().map(|directory| {
+        let mut candidate = if directory.as_os_str().is_empty() { PathBuf::from(".") } else { directory.clone() };
+        candidate.push(name);
+        candidate
+    }).filter(|candidate| check(candidate) == Some(true)).collect()
+}
+
+pub struct HashEntry {
+    pub path: PathBuf,
+    pub hits: usize,
+}
+
+/// Remembers where commands were found, so each one only walks the PATH once
+#[derive(Default)]
+pub struct CommandHash {
+    entries: HashMap<String, HashEntry>,
+    /// The PATH the entries were found with, if it changes they're all stale
+    path_list: Vec<PathBuf>,
+}
+
+impl CommandHash {
+    /// Like `resolve`, but checks the cache first and remembers what's found
+    pub fn resolve(&mut self, name: &str, path_list: &[PathBuf]) -> Resolution {
+        if name.contains('/') {
+            return resolve(name, path_list);
+        }
+
+        if self.path_list != path_list {
+            self.clear();
+            self.path_list = path_list.to_vec();
+        }
+
+        if let Some(entry) = self.entries.get_mut(name) {
+            // The program may have been removed or moved since we found it
+            if let Some(true) = check(&entry.path) {
+                entry.hits += 1;
+                return Resolution::Found(entry.path.clone());
+            }
+            self.entries.remove(name);
+        }
+
+        let resolution = resolve(name, path_list);
+        if let Resolution::Found(ref path) = resolution {
+            self.entries.insert(name.to_string(), HashEntry { path: path.clone(), hits: 1 });
+        }
+        resolution
+    }
+
+    /// Like `resolve`, but without changing the cache
+    pub fn peek(&self, name: &str, path_list: &[PathBuf]) -> Resolution {
+        if !name.contains('/') && self.path_list == path_list {
+            if let Some(entry) = self.entries.get(name) {
+                if let Some(true) = check(&entry.path) {
+                    return Resolution::Found(entry.path.clone());
+                }
+            }
+        }
+
+        resolve(name, path_list)
+    }
+
+    /// Looks the name up and remembers it without counting it as a use
+    pub fn add(&mut self, name: &str, path_list: &[PathBuf]) -> Resolution {
+        let resolution = self.resolve(name, path_list);
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.hits -= 1;
+        }
+        resolution
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Every entry, sorted by name
+    pub fn sorted(&self) -> Vec<(&String, &HashEntry)> {
+        let mut entries: Vec<(&String, &HashEntry)> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
@@ -1,14 +1,16 @@
-use libc;
 use std::ffi::CStr;
-use std::io::Write;
-use std;
-use termcolor::{self, ColorSpec, Color, WriteColor};
+use termcolor::{ColorSpec, Color, WriteColor};
 
-pub fn write_prompt(buf: &mut termcolor::StandardStreamLock, username: &str, user_id: libc::uid_t, home_dir: &str, status: i32) -> Result<(), std::io::Error> {
+use crate::jobs;
+
+pub fn write_prompt<W: WriteColor>(buf: &mut W, username: &str, user_id: libc::uid_t, home_dir: &str, status: i32, signal: Option<i32>) -> Result<(), std::io::Error> {
     // Status
     if status != 0 {
         buf.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
         write!(buf, "{} ", status)?;
+        if let Some(signal) = signal {
+            write!(buf, "{} ", jobs::signal_name(signal))?;
+        }
         buf.reset()?;
     }
 
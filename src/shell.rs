@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::path::PathBuf;
+
+use crate::config::Settings;
+use crate::history::History;
+use crate::jobs::{self, JobTable};
+use crate::builtins;
+use crate::complete::Spec;
+use crate::path::{CommandHash, Resolution};
+
+/// Toggled with `set -o name` and `set +o name`
+pub struct Options {
+    /// Let globs match file names starting with `.`
+    pub dotglob: bool,
+    /// Make a glob that matches nothing an error
+    pub failglob: bool,
+    /// Expand `!!` and friends in lines typed at the terminal
+    pub histexpand: bool,
+    /// Don't record a line in history if it's the same as the one before
+    pub histignoredups: bool,
+    /// Don't record lines starting with a space in history
+    pub histignorespace: bool,
+    /// Remove a glob that matches nothing, instead of leaving it as is
+    pub nullglob: bool,
+    pub pipefail: bool,
+}
+
+impl Options {
+    pub const NAMES: &'static [&'static str] = &["dotglob", "failglob", "histexpand", "histignoredups", "histignorespace", "nullglob", "pipefail"];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "failglob" => Some(&mut self.failglob),
+            "histexpand" => Some(&mut self.histexpand),
+            "histignoredups" => Some(&mut self.histignoredups),
+            "histignorespace" => Some(&mut self.histignorespace),
+            "nullglob" => Some(&mut self.nullglob),
+            "pipefail" => Some(&mut self.pipefail),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "dotglob" => Some(self.dotglob),
+            "failglob" => Some(self.failglob),
+            "histexpand" => Some(self.histexpand),
+            "histignoredups" => Some(self.histignoredups),
+            "histignorespace" => Some(self.histignorespace),
+            "nullglob" => Some(self.nullglob),
+            "pipefail" => Some(self.pipefail),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            dotglob: false,
+            failglob: false,
+            histexpand: true,
+            histignoredups: false,
+            histignorespace: false,
+            nullglob: false,
+            pipefail: false,
+        }
+    }
+}
+
+/// What a command name refers to
+pub enum CommandKind<'a> {
+    Alias(&'a str),
+    Builtin,
+    Program(Resolution),
+}
+
+#[derive(Clone)]
+pub struct Variable {
+    pub value: String,
+    /// Passed on to the environment of commands we run
+    pub exported: bool,
+}
+
+/// Everything that persists from one command to the next
+pub struct Shell {
+    pub home_dir: String,
+    pub path_list: Vec<PathBuf>,
+    pub command_hash: CommandHash,
+    pub variables: HashMap<String, Variable>,
+    /// `$0`
+    pub name: String,
+    /// `$1` and onwards
+    pub positional: Vec<String>,
+    /// `$$`, which stays the same in forked copies of the shell
+    pub pid: libc::pid_t,
+    /// `$!`
+    pub last_background_pid: Option<libc::pid_t>,
+    pub aliases: HashMap<String, String>,
+    /// Completion specs registered with `complete`, by command name
+    pub completions: HashMap<String, Spec>,
+    pub history: History,
+    pub exit_status: i32,
+    /// The signal that killed the last foreground job, if that's what `exit_status` came from
+    pub exit_signal: Option<i32>,
+    pub options: Options,
+    pub jobs: JobTable,
+    /// Only on when we're interactive, and never in a forked copy of the shell
+    pub job_control: bool,
+    pub pgid: libc::pid_t,
+    pub terminal_modes: Option<libc::termios>,
+    pub spawn_file_actions: libc::posix_spawn_file_actions_t,
+    pub spawn_attributes: libc::posix_spawnattr_t,
+}
+
+impl Shell {
+    pub fn new(home_dir: String, settings: Settings) -> Shell {
+        let mut spawn_file_actions: MaybeUninit<libc::posix_spawn_file_actions_t> = MaybeUninit::uninit();
+        let mut spawn_attributes: MaybeUninit<libc::posix_spawnattr_t> = MaybeUninit::uninit();
+
+        let (spawn_file_actions, spawn_attributes) = unsafe {
+            libc::posix_spawn_file_actions_init(spawn_file_actions.as_mut_ptr());
+            libc::posix_spawnattr_init(spawn_attributes.as_mut_ptr());
+
+            // Rust ignores SIGPIPE and job control ignores a few more, which children would otherwise inherit
+            let mut default_signals: MaybeUninit<libc::sigset_t> = MaybeUninit::uninit();
+            libc::sigemptyset(default_signals.as_mut_ptr());
+            libc::sigaddset(default_signals.as_mut_ptr(), libc::SIGPIPE);
+            for &signal in jobs::JOB_CONTROL_SIGNALS {
+                libc::sigaddset(default_signals.as_mut_ptr(), signal);
+            }
+            libc::posix_spawnattr_setsigdefault(spawn_attributes.as_mut_ptr(), default_signals.as_ptr());
+
+            (spawn_file_actions.assume_init(), spawn_attributes.assume_init())
+        };
+
+        // Everything we inherited is passed on, along with what .trashrc adds
+        let mut variables: HashMap<String, Variable> = env::vars_os()
+            .map(|(name, value)| (name.to_string_lossy().into_owned(), Variable { value: value.to_string_lossy().into_owned(), exported: true }))
+            .collect();
+        for (name, value) in settings.exports {
+            variables.insert(name, Variable { value, exported: true });
+        }
+        let path_string = settings.path.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>().join(":");
+        variables.insert("PATH".to_string(), Variable { value: path_string, exported: true });
+        variables.insert("HOME".to_string(), Variable { value: home_dir.clone(), exported: true });
+        if let Ok(dir) = env::current_dir() {
+            variables.insert("PWD".to_string(), Variable { value: dir.to_string_lossy().into_owned(), exported: true });
+        }
+
+        Shell {
+            home_dir,
+            path_list: settings.path,
+            command_hash: CommandHash::default(),
+            variables,
+            name: env::args().next().unwrap_or_else(|| "trash".to_string()),
+            positional: Vec::new(),
+            pid: unsafe { libc::getpid() },
+            last_background_pid: None,
+            aliases: settings.aliases,
+            completions: settings.completions,
+            history: History::default(),
+            exit_status: 0,
+            exit_signal: None,
+            options: settings.options,
+            jobs: JobTable::default(),
+            job_control: false,
+            pgid: unsafe { libc::getpgrp() },
+            terminal_modes: None,
+            spawn_file_actions,
+            spawn_attributes,
+        }
+    }
+
+    /// Resolves a command name the same way running it would: aliases first, then builtins, then the PATH
+    pub fn find_command(&self, name: &str, use_aliases: bool) -> CommandKind<'_> {
+        if use_aliases {
+            if let Some(replacement) = self.aliases.get(name) {
+                return CommandKind::Alias(replacement);
+            }
+        }
+
+        if builtins::is_builtin(name) {
+            return CommandKind::Builtin;
+        }
+
+        CommandKind::Program(self.command_hash.peek(name, &self.path_list))
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(|variable| variable.value.as_str())
+    }
+
+    /// Sets a variable, keeping whether it's exported
+    pub fn set_variable(&mut self, name: &str, value: String) {
+        match self.variables.get_mut(name) {
+            Some(variable) => variable.value = value,
+            None => {
+                self.variables.insert(name.to_string(), Variable { value, exported: false });
+            },
+        }
+        self.variable_changed(name);
+    }
+
+    pub fn unset_variable(&mut self, name: &str) -> Option<Variable> {
+        let variable = self.variables.remove(name);
+        self.variable_changed(name);
+        variable
+    }
+
+    /// Puts a variable back how it was before, as returned by `unset_variable` or read from `variables`
+    pub fn restore_variable(&mut self, name: &str, variable: Option<Variable>) {
+        match variable {
+            Some(variable) => {
+                self.variables.insert(name.to_string(), variable);
+                self.variable_changed(name);
+            },
+            None => {
+                self.unset_variable(name);
+            },
+        }
+    }
+
+    /// Keeps anything derived from a variable in sync with it
+    fn variable_changed(&mut self, name: &str) {
+        if name == "PATH" {
+            // The command hash notices the new list and starts over
+            self.path_list = match self.variable("PATH") {
+                Some(value) => value.split(':').map(PathBuf::from).collect(),
+                None => Vec::new(),
+            };
+        }
+    }
+
+    /// The environment for a command: every exported variable, plus `extra` which takes precedence
+    pub fn environment(&self, extra: &[(String, String)]) -> Vec<CString> {
+        let exported = self.variables.iter()
+            .filter(|(name, variable)| variable.exported && !extra.iter().any(|(extra_name, _)| extra_name == *name))
+            .map(|(name, variable)| (name.as_str(), variable.value.as_str()));
+        let extra = extra.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+
+        // Anything with a null byte in it can't be passed on, so it's left out
+        exported.chain(extra).filter_map(|(name, value)| CString::new(format!("{}={}", name, value)).ok()).collect()
+    }
+}
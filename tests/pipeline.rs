@@ -0,0 +1,43 @@
+use std::env;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs `trash -c source` with an empty home directory, killing it and anything it started if it runs past the timeout.
+/// None if it had to be killed
+fn run(source: &str, timeout: Duration) -> Option<ExitStatus> {
+    let home_dir = env::temp_dir().join("trash-test-home");
+    fs::create_dir_all(&home_dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_trash"))
+        .arg("-c")
+        .arg(source)
+        .env("HOME", &home_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().unwrap() {
+            return Some(status);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+    let _ = child.wait();
+    None
+}
+
+#[test]
+fn builtin_sees_reader_exit() {
+    // More than a pipe buffer's worth, so the builtin blocks for good unless it gets SIGPIPE once head is gone
+    let source = format!("command -v {} | head -c1; echo done", vec!["cd"; 40000].join(" "));
+    let status = run(&source, Duration::from_secs(10)).expect("pipeline never finished");
+    assert!(status.success());
+}